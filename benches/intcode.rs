@@ -0,0 +1,173 @@
+use aoc_2019::intcode::{compiled::CompiledVM, IntcodeVM};
+use criterion::{criterion_group, criterion_main, Criterion};
+use permutohedron::Heap;
+use std::hint::black_box;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Engine {
+    Reference,
+    Uncached,
+    Cached,
+    Compiled,
+}
+
+fn program(input: &str) -> Vec<i64> {
+    input
+        .trim()
+        .split(',')
+        .map(|s| s.parse().unwrap())
+        .collect()
+}
+
+// the interpreter as it was before the decode cache: a plain vector, decoded afresh every step,
+// so the other engines have something to be compared against
+fn reference_run(
+    program: &[i64],
+    input: &mut impl FnMut() -> Option<i64>,
+    output: &mut impl FnMut(i64),
+) {
+    let mut memory = program.to_vec();
+    let (mut ip, mut rel) = (0, 0);
+
+    fn address(memory: &mut Vec<i64>, mode: i64, ip: usize, rel: i64) -> usize {
+        let address = match mode {
+            0 => memory[ip] as usize,
+            1 => ip,
+            _ => (rel + memory[ip]) as usize,
+        };
+
+        if address >= memory.len() {
+            memory.resize(address + 1, 0);
+        }
+
+        address
+    }
+
+    loop {
+        let instruction = memory[ip];
+        let mode = |n: u32| instruction / 10_i64.pow(n + 1) % 10;
+        let arg = |memory: &mut Vec<i64>, n| address(memory, mode(n as u32), ip + n, rel);
+
+        match instruction % 100 {
+            op @ (1 | 2 | 7 | 8) => {
+                let (a, b, dest) = (
+                    arg(&mut memory, 1),
+                    arg(&mut memory, 2),
+                    arg(&mut memory, 3),
+                );
+                let (a, b) = (memory[a], memory[b]);
+
+                memory[dest] = match op {
+                    1 => a + b,
+                    2 => a * b,
+                    7 => (a < b) as i64,
+                    _ => (a == b) as i64,
+                };
+                ip += 4;
+            }
+            3 => {
+                let dest = arg(&mut memory, 1);
+                memory[dest] = input().unwrap();
+                ip += 2;
+            }
+            4 => {
+                let value = arg(&mut memory, 1);
+                output(memory[value]);
+                ip += 2;
+            }
+            op @ (5 | 6) => {
+                let (value, target) = (arg(&mut memory, 1), arg(&mut memory, 2));
+
+                if (memory[value] != 0) == (op == 5) {
+                    ip = memory[target] as usize;
+                } else {
+                    ip += 3;
+                }
+            }
+            9 => {
+                let value = arg(&mut memory, 1);
+                rel += memory[value];
+                ip += 2;
+            }
+            _ => break,
+        }
+    }
+}
+
+fn last_output(program: &[i64], inputs: &[i64], engine: Engine) -> i64 {
+    let mut inputs = inputs.iter().copied();
+    let mut last = 0;
+
+    {
+        let mut input = || inputs.next();
+        let mut output = |value| last = value;
+
+        match engine {
+            Engine::Reference => reference_run(program, &mut input, &mut output),
+            Engine::Compiled => {
+                CompiledVM::new(program.to_vec())
+                    .run_with(&mut input, &mut output)
+                    .unwrap();
+            }
+            _ => {
+                let mut vm = IntcodeVM::new(program.to_vec());
+                vm.set_decode_cache(engine == Engine::Cached);
+                vm.run_with(&mut input, &mut output).unwrap();
+            }
+        }
+    }
+
+    last
+}
+
+fn amplifiers(program: &[i64], engine: Engine) -> i64 {
+    let mut phases = vec![0, 1, 2, 3, 4];
+
+    Heap::new(&mut phases)
+        .map(|phases| {
+            phases.iter().fold(0, |signal, &phase| {
+                last_output(program, &[phase, signal], engine)
+            })
+        })
+        .max()
+        .unwrap()
+}
+
+// what these measured last time they were looked at:
+//
+//              day5      day7      day9
+// reference    0.78 us   82 us     2.34 ms
+// uncached     1.85 us   189 us    5.38 ms
+// cached       1.85 us   189 us    2.83 ms
+// compiled     2.67 us   228 us    2.01 ms
+//
+// day5 and day7 are over long before the decode cache warms up or anything gets hot enough to
+// compile, so those mostly measure setting a VM up. day9 is the only one long enough for the cache
+// and the compiled engine to pay off
+fn bench_engines(c: &mut Criterion) {
+    let day5 = program(include_str!("../input/2019/day5.txt"));
+    let day7 = program(include_str!("../input/2019/day7.txt"));
+    let day9 = program(include_str!("../input/2019/day9.txt"));
+
+    for &(engine, name) in &[
+        (Engine::Reference, "reference"),
+        (Engine::Uncached, "uncached"),
+        (Engine::Cached, "cached"),
+        (Engine::Compiled, "compiled"),
+    ] {
+        let mut group = c.benchmark_group(name);
+
+        group.bench_function("day5", |b| {
+            b.iter(|| last_output(black_box(&day5), &[5], engine))
+        });
+        group.bench_function("day7", |b| b.iter(|| amplifiers(black_box(&day7), engine)));
+        group.bench_function("day9", |b| {
+            b.iter(|| last_output(black_box(&day9), &[2], engine))
+        });
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_engines);
+criterion_main!(benches);
@@ -0,0 +1,12 @@
+#![no_main]
+
+use aoc_2019::intcode::differential::{check, generate, STEP_BUDGET};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let case = generate(data);
+
+    if let Err(mismatch) = check(&case, STEP_BUDGET) {
+        panic!("{}\nin {:?}", mismatch, case);
+    }
+});
@@ -1,4 +1,4 @@
-use super::intcode::IntcodeVM;
+use super::intcode::{IntcodeError, IntcodeVM};
 use aoc_runner_derive::{aoc, aoc_generator};
 
 #[aoc_generator(day5)]
@@ -7,17 +7,17 @@ pub fn generator(input: &str) -> Vec<i64> {
 }
 
 #[aoc(day5, part1)]
-pub fn part1(input: &[i64]) -> i64 {
+pub fn part1(input: &[i64]) -> Result<i64, IntcodeError> {
     let memory: Vec<i64> = input.iter().copied().collect();
     let mut vm = IntcodeVM::new(memory);
     vm.input(1);
-    vm.get_next_output().unwrap()
+    Ok(vm.get_next_output()?.unwrap())
 }
 
 #[aoc(day5, part2)]
-pub fn part2(input: &[i64]) -> i64 {
+pub fn part2(input: &[i64]) -> Result<i64, IntcodeError> {
     let memory: Vec<i64> = input.iter().copied().collect();
     let mut vm = IntcodeVM::new(memory);
     vm.input(5);
-    vm.get_next_output().unwrap()
+    Ok(vm.get_next_output()?.unwrap())
 }
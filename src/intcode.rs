@@ -1,16 +1,77 @@
-use std::collections::VecDeque;
+use extension::{Custom, Extensions, MAX_PARAMETERS};
+use memory::{Backend, MemoryBackend, SparseMemory};
+use profile::Profile;
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    ops::Range,
+    time::{Duration, Instant},
+};
+use trace::{Journal, Trace, TraceEntry};
+use watch::{Access, WatchHit, WatchKind, Watchpoints};
+
+pub mod ascii;
+pub mod asm;
+pub mod cfg;
+pub mod compiled;
+pub mod debugger;
+pub mod decompile;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod differential;
+pub mod disasm;
+pub mod extension;
+pub mod io;
+pub mod memory;
+pub mod network;
+pub mod nounverb;
+pub mod pipeline;
+pub mod profile;
+pub mod search;
+pub mod snapshot;
+pub mod trace;
+pub mod watch;
+
+// decoded instructions are cached for addresses below this
+const DECODE_CACHE_LIMIT: usize = 1 << 16;
+// short runs are over before the cache pays for itself, so nothing is cached until this many
+// instructions have been decoded without it
+const DECODE_CACHE_WARMUP: usize = 2048;
+// opcode and three parameters
+const MAX_INSTRUCTION_LEN: usize = 4;
+// how many steps there are between reading the clock when there's a time limit
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
 
 #[derive(Debug)]
 pub struct IntcodeVM {
     memory: Memory,
     ip: usize,
     input: VecDeque<i64>,
-    rel_base_offset: usize,
+    rel_base_offset: i64,
+    watch_hits: VecDeque<WatchHit>,
+    trace: Option<Trace>,
+    profile: Option<Profile>,
+    steps: u64,
+    // the step count the budget runs out at
+    step_limit: Option<u64>,
+    deadline: Option<Instant>,
+    // the limits only need looking at once this many steps have been executed
+    next_limit_check: u64,
+    extensions: Extensions,
+    // what stopped another VM reading from this one as an input device
+    device_error: Option<IntcodeError>,
 }
 
 #[derive(Debug)]
 struct Memory {
-    memory: Vec<i64>,
+    backend: Backend,
+    watchpoints: Watchpoints,
+    journal: Option<Journal>,
+    // whether watchpoints or a journal need to see accesses, so plain runs only check this
+    observed: bool,
+    decoded: Vec<Option<Opcode>>,
+    cache_decoded: bool,
+    uncached_decodes: usize,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -25,22 +86,95 @@ enum Opcode {
     Equals(Parameter, Parameter, Parameter),
     RelativeBaseOffset(Parameter),
     Halt,
+    Custom(Custom),
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum Parameter {
-    Position(usize),
-    Immediate(usize),
-    Relative(usize),
+    Position(i64),
+    Immediate(i64),
+    Relative(i64),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IntcodeError {
+    UnknownOpcode {
+        ip: usize,
+        instruction: i64,
+    },
+    InvalidMode {
+        ip: usize,
+        instruction: i64,
+        mode: i64,
+    },
+    WriteToImmediate {
+        ip: usize,
+        instruction: i64,
+    },
+    InputUnderflow {
+        ip: usize,
+        instruction: i64,
+    },
+    NegativeAddress {
+        ip: usize,
+        instruction: i64,
+        address: i64,
+    },
+    // a registered instruction's handler asked for a parameter the instruction doesn't have
+    BadOperand {
+        ip: usize,
+        instruction: i64,
+        operand: usize,
+    },
+    // the instruction at ip hasn't been executed, so raising the limit and running again
+    // carries on from where the VM stopped
+    BudgetExhausted {
+        ip: usize,
+        instruction: i64,
+        limit: Limit,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Limit {
+    Steps,
+    Time,
+}
+
+// an error without the location it happened at, the VM fills that in
+#[derive(Debug, Copy, Clone)]
+enum Fault {
+    UnknownOpcode,
+    InvalidMode(i64),
+    WriteToImmediate,
+    NegativeAddress(i64),
+    BadOperand(usize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RunState {
+    NeedsInput,
+    Output(i64),
+    Halted,
+    Watchpoint(WatchHit),
+}
+
+// the parts of the VM every step changes
+#[derive(Debug, Copy, Clone)]
+struct Registers {
+    ip: usize,
+    rel_base_offset: i64,
+    steps: u64,
 }
 
 #[derive(Debug, Copy, Clone)]
 enum OpcodeOutput {
     None,
     Halt,
+    NeedsInput,
     Output(i64),
     Jump(usize),
-    NewBaseOffset(usize),
+    NewBaseOffset(i64),
 }
 
 fn ones(val: i64) -> i64 {
@@ -63,129 +197,307 @@ fn tens_thousands(val: i64) -> i64 {
     val % 100_000 / 10_000
 }
 
+fn address(value: i64) -> Result<usize, Fault> {
+    if value < 0 {
+        Err(Fault::NegativeAddress(value))
+    } else {
+        Ok(value as usize)
+    }
+}
+
+impl Registers {
+    // moves past an executed instruction and counts it, returns why the run should stop if it
+    // should
+    #[inline(always)]
+    fn advance(&mut self, opcode: Opcode, output: OpcodeOutput) -> Option<RunState> {
+        let state = match output {
+            OpcodeOutput::NeedsInput => return Some(RunState::NeedsInput),
+            OpcodeOutput::Halt => Some(RunState::Halted),
+            OpcodeOutput::Output(value) => {
+                self.ip += opcode.len();
+                Some(RunState::Output(value))
+            }
+            OpcodeOutput::Jump(ip) => {
+                self.ip = ip;
+                None
+            }
+            OpcodeOutput::NewBaseOffset(new_base) => {
+                self.rel_base_offset = new_base;
+                self.ip += opcode.len();
+                None
+            }
+            OpcodeOutput::None => {
+                self.ip += opcode.len();
+                None
+            }
+        };
+
+        self.steps += 1;
+        state
+    }
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownOpcode { ip, instruction } => {
+                write!(f, "unknown opcode (instruction {} at {})", instruction, ip)
+            }
+            Self::InvalidMode {
+                ip,
+                instruction,
+                mode,
+            } => write!(
+                f,
+                "invalid parameter mode {} (instruction {} at {})",
+                mode, instruction, ip
+            ),
+            Self::WriteToImmediate { ip, instruction } => write!(
+                f,
+                "write to an immediate mode parameter (instruction {} at {})",
+                instruction, ip
+            ),
+            Self::InputUnderflow { ip, instruction } => write!(
+                f,
+                "input requested but the input queue is empty (instruction {} at {})",
+                instruction, ip
+            ),
+            Self::NegativeAddress {
+                ip,
+                instruction,
+                address,
+            } => write!(
+                f,
+                "negative address {} (instruction {} at {})",
+                address, instruction, ip
+            ),
+            Self::BadOperand {
+                ip,
+                instruction,
+                operand,
+            } => write!(
+                f,
+                "no parameter {} (instruction {} at {})",
+                operand, instruction, ip
+            ),
+            Self::BudgetExhausted {
+                ip,
+                instruction,
+                limit,
+            } => write!(
+                f,
+                "{} exhausted (instruction {} at {})",
+                limit, instruction, ip
+            ),
+        }
+    }
+}
+
+impl Error for IntcodeError {}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Steps => write!(f, "step budget"),
+            Self::Time => write!(f, "time limit"),
+        }
+    }
+}
+
+impl Fault {
+    fn at(self, ip: usize, instruction: i64) -> IntcodeError {
+        match self {
+            Self::UnknownOpcode => IntcodeError::UnknownOpcode { ip, instruction },
+            Self::InvalidMode(mode) => IntcodeError::InvalidMode {
+                ip,
+                instruction,
+                mode,
+            },
+            Self::WriteToImmediate => IntcodeError::WriteToImmediate { ip, instruction },
+            Self::NegativeAddress(address) => IntcodeError::NegativeAddress {
+                ip,
+                instruction,
+                address,
+            },
+            Self::BadOperand(operand) => IntcodeError::BadOperand {
+                ip,
+                instruction,
+                operand,
+            },
+        }
+    }
+}
+
 impl Opcode {
-    fn from_memory(index: usize, memory: &mut Memory) -> Self {
-        let opcode_value = memory.get(index);
+    #[inline(always)]
+    fn from_memory(index: usize, memory: &Memory, extensions: &Extensions) -> Result<Self, Fault> {
+        Self::decode_with(
+            index,
+            |index| memory.fetch(index),
+            |number| extensions.parameters(number),
+        )
+    }
+
+    // only knows the built in opcodes
+    fn decode<F>(index: usize, fetch: F) -> Result<Self, Fault>
+    where
+        F: FnMut(usize) -> i64,
+    {
+        Self::decode_with(index, fetch, |_| None)
+    }
+
+    // custom gives the parameter count of registered opcodes
+    #[inline(always)]
+    fn decode_with<F, C>(index: usize, mut fetch: F, custom: C) -> Result<Self, Fault>
+    where
+        F: FnMut(usize) -> i64,
+        C: Fn(i64) -> Option<usize>,
+    {
+        let opcode_value = fetch(index);
         let opcode = (tens(opcode_value) * 10) + ones(opcode_value);
 
-        match opcode {
+        Ok(match opcode {
             1 => Self::Add(
-                Parameter::new(hundreds(opcode_value), memory.get(index + 1)),
-                Parameter::new(thousands(opcode_value), memory.get(index + 2)),
-                Parameter::new(tens_thousands(opcode_value), memory.get(index + 3)),
+                Parameter::new(hundreds(opcode_value), fetch(index + 1))?,
+                Parameter::new(thousands(opcode_value), fetch(index + 2))?,
+                Parameter::new(tens_thousands(opcode_value), fetch(index + 3))?,
             ),
             2 => Self::Mul(
-                Parameter::new(hundreds(opcode_value), memory.get(index + 1)),
-                Parameter::new(thousands(opcode_value), memory.get(index + 2)),
-                Parameter::new(tens_thousands(opcode_value), memory.get(index + 3)),
+                Parameter::new(hundreds(opcode_value), fetch(index + 1))?,
+                Parameter::new(thousands(opcode_value), fetch(index + 2))?,
+                Parameter::new(tens_thousands(opcode_value), fetch(index + 3))?,
             ),
-            3 => Self::Input(Parameter::new(
-                hundreds(opcode_value),
-                memory.get(index + 1),
-            )),
-            4 => Self::Output(Parameter::new(
-                hundreds(opcode_value),
-                memory.get(index + 1),
-            )),
+            3 => Self::Input(Parameter::new(hundreds(opcode_value), fetch(index + 1))?),
+            4 => Self::Output(Parameter::new(hundreds(opcode_value), fetch(index + 1))?),
             5 => Self::JumpIfTrue(
-                Parameter::new(hundreds(opcode_value), memory.get(index + 1)),
-                Parameter::new(thousands(opcode_value), memory.get(index + 2)),
+                Parameter::new(hundreds(opcode_value), fetch(index + 1))?,
+                Parameter::new(thousands(opcode_value), fetch(index + 2))?,
             ),
             6 => Self::JumpIfFalse(
-                Parameter::new(hundreds(opcode_value), memory.get(index + 1)),
-                Parameter::new(thousands(opcode_value), memory.get(index + 2)),
+                Parameter::new(hundreds(opcode_value), fetch(index + 1))?,
+                Parameter::new(thousands(opcode_value), fetch(index + 2))?,
             ),
             7 => Self::LessThan(
-                Parameter::new(hundreds(opcode_value), memory.get(index + 1)),
-                Parameter::new(thousands(opcode_value), memory.get(index + 2)),
-                Parameter::new(tens_thousands(opcode_value), memory.get(index + 3)),
+                Parameter::new(hundreds(opcode_value), fetch(index + 1))?,
+                Parameter::new(thousands(opcode_value), fetch(index + 2))?,
+                Parameter::new(tens_thousands(opcode_value), fetch(index + 3))?,
             ),
             8 => Self::Equals(
-                Parameter::new(hundreds(opcode_value), memory.get(index + 1)),
-                Parameter::new(thousands(opcode_value), memory.get(index + 2)),
-                Parameter::new(tens_thousands(opcode_value), memory.get(index + 3)),
+                Parameter::new(hundreds(opcode_value), fetch(index + 1))?,
+                Parameter::new(thousands(opcode_value), fetch(index + 2))?,
+                Parameter::new(tens_thousands(opcode_value), fetch(index + 3))?,
             ),
-            9 => Self::RelativeBaseOffset(Parameter::new(
-                hundreds(opcode_value),
-                memory.get(index + 1),
-            )),
+            9 => {
+                Self::RelativeBaseOffset(Parameter::new(hundreds(opcode_value), fetch(index + 1))?)
+            }
             99 => Self::Halt,
-            _ => panic!(
-                "something went terribly wrong (opcode {} at {})",
-                opcode, index
-            ),
-        }
+            number => {
+                let count = custom(number).ok_or(Fault::UnknownOpcode)?;
+                let modes = [
+                    hundreds(opcode_value),
+                    thousands(opcode_value),
+                    tens_thousands(opcode_value),
+                ];
+                let mut parameters = [Parameter::Immediate(0); MAX_PARAMETERS];
+
+                for (i, parameter) in parameters.iter_mut().enumerate().take(count) {
+                    *parameter = Parameter::new(modes[i], fetch(index + 1 + i))?;
+                }
+
+                // the registered numbers all fit in two digits
+                Self::Custom(Custom {
+                    number: number as u8,
+                    count: count as u8,
+                    parameters,
+                })
+            }
+        })
     }
 
+    #[inline(always)]
     fn execute(
         self,
         memory: &mut Memory,
         input: &mut VecDeque<i64>,
-        rel_base_offset: usize,
-    ) -> OpcodeOutput {
-        match self {
+        rel_base_offset: i64,
+    ) -> Result<OpcodeOutput, Fault> {
+        Ok(match self {
             Self::Add(p1, p2, dest) => {
-                let value =
-                    p1.evaluate(memory, rel_base_offset) + p2.evaluate(memory, rel_base_offset);
-                memory.set(dest.position(rel_base_offset), value);
+                let value = p1
+                    .evaluate(memory, rel_base_offset)?
+                    .wrapping_add(p2.evaluate(memory, rel_base_offset)?);
+                memory.set(dest.position(rel_base_offset)?, value);
 
                 OpcodeOutput::None
             }
             Self::Mul(p1, p2, dest) => {
-                let value =
-                    p1.evaluate(memory, rel_base_offset) * p2.evaluate(memory, rel_base_offset);
-                memory.set(dest.position(rel_base_offset), value);
+                let value = p1
+                    .evaluate(memory, rel_base_offset)?
+                    .wrapping_mul(p2.evaluate(memory, rel_base_offset)?);
+                memory.set(dest.position(rel_base_offset)?, value);
 
                 OpcodeOutput::None
             }
             Self::Input(dest) => {
-                memory.set(dest.position(rel_base_offset), input.pop_front().unwrap());
-                OpcodeOutput::None
+                let position = dest.position(rel_base_offset)?;
+
+                // leave the instruction pointer where it is so the input can be retried once
+                // there's something in the queue
+                match input.pop_front() {
+                    Some(value) => {
+                        memory.set(position, value);
+                        OpcodeOutput::None
+                    }
+                    None => OpcodeOutput::NeedsInput,
+                }
             }
-            Self::Output(dest) => OpcodeOutput::Output(dest.evaluate(memory, rel_base_offset)),
+            Self::Output(dest) => OpcodeOutput::Output(dest.evaluate(memory, rel_base_offset)?),
             Self::JumpIfTrue(p1, p2) => {
-                if p1.evaluate(memory, rel_base_offset) != 0 {
-                    OpcodeOutput::Jump(p2.evaluate(memory, rel_base_offset) as usize)
+                if p1.evaluate(memory, rel_base_offset)? != 0 {
+                    OpcodeOutput::Jump(address(p2.evaluate(memory, rel_base_offset)?)?)
                 } else {
                     OpcodeOutput::None
                 }
             }
             Self::JumpIfFalse(p1, p2) => {
-                if p1.evaluate(memory, rel_base_offset) == 0 {
-                    OpcodeOutput::Jump(p2.evaluate(memory, rel_base_offset) as usize)
+                if p1.evaluate(memory, rel_base_offset)? == 0 {
+                    OpcodeOutput::Jump(address(p2.evaluate(memory, rel_base_offset)?)?)
                 } else {
                     OpcodeOutput::None
                 }
             }
             Self::LessThan(p1, p2, dest) => {
-                let value = if p1.evaluate(memory, rel_base_offset)
-                    < p2.evaluate(memory, rel_base_offset)
+                let value = if p1.evaluate(memory, rel_base_offset)?
+                    < p2.evaluate(memory, rel_base_offset)?
                 {
                     1
                 } else {
                     0
                 };
-                memory.set(dest.position(rel_base_offset), value);
+                memory.set(dest.position(rel_base_offset)?, value);
 
                 OpcodeOutput::None
             }
             Self::Equals(p1, p2, dest) => {
-                let value = if p1.evaluate(memory, rel_base_offset)
-                    == p2.evaluate(memory, rel_base_offset)
+                let value = if p1.evaluate(memory, rel_base_offset)?
+                    == p2.evaluate(memory, rel_base_offset)?
                 {
                     1
                 } else {
                     0
                 };
-                memory.set(dest.position(rel_base_offset), value);
+                memory.set(dest.position(rel_base_offset)?, value);
 
                 OpcodeOutput::None
             }
             Self::RelativeBaseOffset(p1) => OpcodeOutput::NewBaseOffset(
-                rel_base_offset + p1.evaluate(memory, rel_base_offset) as usize,
+                rel_base_offset.saturating_add(p1.evaluate(memory, rel_base_offset)?),
             ),
             Self::Halt => OpcodeOutput::Halt,
-        }
+            // these need the handlers, which the VM runs itself. without them there's nothing to
+            // run
+            Self::Custom(_) => return Err(Fault::UnknownOpcode),
+        })
     }
 
     fn len(self) -> usize {
@@ -195,68 +507,203 @@ impl Opcode {
             Self::JumpIfTrue(..) | Self::JumpIfFalse(..) => 2,
             Self::Input(..) | Self::Output(..) | Self::RelativeBaseOffset(..) => 1,
             Self::Halt => 0,
+            Self::Custom(custom) => custom.count as usize,
+        }
+    }
+
+    fn mnemonic(self) -> &'static str {
+        match self {
+            Self::Add(..) => "ADD",
+            Self::Mul(..) => "MUL",
+            Self::Input(..) => "INPUT",
+            Self::Output(..) => "OUTPUT",
+            Self::JumpIfTrue(..) => "JUMP_IF_TRUE",
+            Self::JumpIfFalse(..) => "JUMP_IF_FALSE",
+            Self::LessThan(..) => "LESS_THAN",
+            Self::Equals(..) => "EQUALS",
+            Self::RelativeBaseOffset(..) => "RELATIVE_BASE_OFFSET",
+            Self::Halt => "HALT",
+            // the real name is only known to the VM it was registered on
+            Self::Custom(_) => "CUSTOM",
+        }
+    }
+
+    fn parameters(self) -> Vec<Parameter> {
+        match self {
+            Self::Add(p1, p2, p3)
+            | Self::Mul(p1, p2, p3)
+            | Self::LessThan(p1, p2, p3)
+            | Self::Equals(p1, p2, p3) => vec![p1, p2, p3],
+            Self::JumpIfTrue(p1, p2) | Self::JumpIfFalse(p1, p2) => vec![p1, p2],
+            Self::Input(p1) | Self::Output(p1) | Self::RelativeBaseOffset(p1) => vec![p1],
+            Self::Halt => Vec::new(),
+            Self::Custom(custom) => custom.parameters().to_vec(),
         }
     }
 }
 
 impl Parameter {
-    fn new(mode: i64, value: i64) -> Self {
+    fn new(mode: i64, value: i64) -> Result<Self, Fault> {
         match mode {
-            0 => Self::Position(value as usize),
-            1 => Self::Immediate(value as usize),
-            2 => Self::Relative(value as usize),
-            _ => panic!(),
+            0 => Ok(Self::Position(value)),
+            1 => Ok(Self::Immediate(value)),
+            2 => Ok(Self::Relative(value)),
+            _ => Err(Fault::InvalidMode(mode)),
         }
     }
 
-    fn evaluate(self, memory: &mut Memory, rel_base_offset: usize) -> i64 {
+    // loads and stores are most of what the VM does, so the valid cases are checked first and the
+    // fault is only worked out once something is wrong
+    #[inline]
+    fn evaluate(self, memory: &mut Memory, rel_base_offset: i64) -> Result<i64, Fault> {
         match self {
-            Self::Position(value) => memory.get(value),
-            Self::Immediate(value) => value as i64,
-            Self::Relative(value) => memory.get(value + rel_base_offset),
+            Self::Immediate(value) => Ok(value),
+            Self::Position(value) if value >= 0 => Ok(memory.get(value as usize)),
+            _ => Ok(memory.get(self.position(rel_base_offset)?)),
         }
     }
 
-    fn position(self, rel_base_offset: usize) -> usize {
-        match self {
+    // the effective address of a position or relative parameter
+    #[inline]
+    fn position(self, rel_base_offset: i64) -> Result<usize, Fault> {
+        let address = match self {
             Self::Position(value) => value,
-            Self::Relative(value) => value + rel_base_offset,
-            _ => panic!(),
+            Self::Relative(value) => rel_base_offset.saturating_add(value),
+            Self::Immediate(_) => return Err(self.fault(rel_base_offset)),
+        };
+
+        if address >= 0 {
+            Ok(address as usize)
+        } else {
+            Err(self.fault(rel_base_offset))
         }
     }
-}
 
-impl Memory {
-    fn expand_to(&mut self, capacity: usize) {
-        let mut extension = vec![0; capacity - self.memory.len()];
-        self.memory.reserve(extension.len());
-        self.memory.append(&mut extension);
+    #[cold]
+    fn fault(self, rel_base_offset: i64) -> Fault {
+        match self {
+            Self::Position(value) => Fault::NegativeAddress(value),
+            Self::Relative(value) => Fault::NegativeAddress(rel_base_offset.saturating_add(value)),
+            Self::Immediate(_) => Fault::WriteToImmediate,
+        }
     }
+}
 
+impl Memory {
+    // reads and writes done by instructions go through get and set so watchpoints see them,
+    // instruction fetches and debugger accesses use the unwatched fetch and store
     fn get(&mut self, index: usize) -> i64 {
-        if self.memory.len() <= index {
-            self.expand_to(index + 1);
+        let value = self.fetch(index);
+
+        if self.observed {
+            self.observe(index, Access::Read, value);
         }
 
-        self.memory[index]
+        value
     }
 
     fn set(&mut self, index: usize, value: i64) {
-        if self.memory.len() <= index {
-            self.expand_to(index + 1);
+        self.store(index, value);
+
+        if self.observed {
+            self.observe(index, Access::Write, value);
+        }
+    }
+
+    #[inline(never)]
+    fn observe(&mut self, index: usize, access: Access, value: i64) {
+        self.watchpoints.check(index, access, value);
+
+        if let Some(journal) = &mut self.journal {
+            match access {
+                Access::Read => journal.reads.push((index, value)),
+                Access::Write => journal.writes.push((index, value)),
+            }
+        }
+    }
+
+    fn update_observed(&mut self) {
+        self.observed = !self.watchpoints.is_empty() || self.journal.is_some();
+    }
+
+    fn fetch(&self, index: usize) -> i64 {
+        self.backend.get(index)
+    }
+
+    fn store(&mut self, index: usize, value: i64) {
+        self.backend.set(index, value);
+
+        // any cached instruction starting up to three words before this one might include it
+        let end = index.saturating_add(1);
+        let start = end.saturating_sub(MAX_INSTRUCTION_LEN);
+        let end = end.min(self.decoded.len());
+
+        if start < end {
+            for cached in &mut self.decoded[start..end] {
+                *cached = None;
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn decode(&mut self, index: usize, extensions: &Extensions) -> Result<Opcode, Fault> {
+        if let Some(Some(opcode)) = self.decoded.get(index) {
+            return Ok(*opcode);
         }
 
-        self.memory[index] = value;
+        let opcode = Opcode::from_memory(index, self, extensions)?;
+
+        if self.uncached_decodes < DECODE_CACHE_WARMUP {
+            self.uncached_decodes += 1;
+        } else if self.cache_decoded && index < DECODE_CACHE_LIMIT {
+            if self.decoded.len() <= index {
+                self.decoded.resize(index + 1, None);
+            }
+
+            self.decoded[index] = Some(opcode);
+        }
+
+        Ok(opcode)
+    }
+
+    fn replace_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+        self.decoded.clear();
     }
 }
 
 impl IntcodeVM {
     pub fn new(memory: Vec<i64>) -> IntcodeVM {
+        IntcodeVM::with_backend(Backend::Sparse(SparseMemory::new(memory)))
+    }
+
+    pub fn with_memory<M: MemoryBackend + 'static>(memory: M) -> IntcodeVM {
+        IntcodeVM::with_backend(Backend::Boxed(Box::new(memory)))
+    }
+
+    fn with_backend(backend: Backend) -> IntcodeVM {
         IntcodeVM {
-            memory: Memory { memory },
+            memory: Memory {
+                backend,
+                watchpoints: Watchpoints::default(),
+                journal: None,
+                observed: false,
+                decoded: Vec::new(),
+                cache_decoded: true,
+                uncached_decodes: 0,
+            },
             ip: 0,
             input: VecDeque::new(),
             rel_base_offset: 0,
+            watch_hits: VecDeque::new(),
+            trace: None,
+            profile: None,
+            steps: 0,
+            step_limit: None,
+            deadline: None,
+            next_limit_check: u64::MAX,
+            extensions: Extensions::default(),
+            device_error: None,
         }
     }
 
@@ -264,25 +711,745 @@ impl IntcodeVM {
         self.input.push_back(value);
     }
 
-    pub fn get_next_output(&mut self) -> Option<i64> {
+    // memory accesses from outside the program, which watchpoints and traces don't see. writes
+    // over instructions are picked up the next time they're executed
+    pub fn read(&self, address: usize) -> i64 {
+        self.memory.fetch(address)
+    }
+
+    pub fn write(&mut self, address: usize, value: i64) {
+        self.memory.store(address, value);
+    }
+
+    // words that were never written read as zero, the same as with read. they're only read as the
+    // iterator gets to them, so a range can reach as far as memory does
+    pub fn dump(&self, range: Range<usize>) -> impl Iterator<Item = i64> + '_ {
+        range.map(move |index| self.memory.fetch(index))
+    }
+
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.memory.cache_decoded = enabled;
+        self.memory.decoded.clear();
+    }
+
+    pub fn add_watchpoint(&mut self, range: Range<usize>, kind: WatchKind) -> usize {
+        let id = self.memory.watchpoints.add(range, kind);
+        self.memory.update_observed();
+        id
+    }
+
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        let removed = self.memory.watchpoints.remove(id);
+        self.memory.update_observed();
+        removed
+    }
+
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Trace::default());
+    }
+
+    // only the last capacity instructions are kept, the older ones are counted in dropped
+    pub fn start_trace_with_capacity(&mut self, capacity: usize) {
+        self.trace = Some(Trace::with_capacity(capacity));
+    }
+
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    // how many instructions have been executed in total
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // allows this many more instructions to be executed before runs fail with BudgetExhausted
+    pub fn set_step_budget(&mut self, steps: Option<u64>) {
+        self.step_limit = steps.map(|steps| self.steps.saturating_add(steps));
+        self.schedule_limit_check(self.steps);
+    }
+
+    pub fn step_budget(&self) -> Option<u64> {
+        self.step_limit
+            .map(|limit| limit.saturating_sub(self.steps))
+    }
+
+    // runs fail with BudgetExhausted once this much time has passed from now. the clock is only
+    // checked every so often, so the limit can be overshot by a few microseconds. a limit too far
+    // off for the clock to represent is the same as none
+    pub fn set_time_limit(&mut self, limit: Option<Duration>) {
+        self.deadline = limit.and_then(|limit| Instant::now().checked_add(limit));
+        self.schedule_limit_check(self.steps);
+    }
+
+    pub fn run(&mut self) -> Result<RunState, IntcodeError> {
+        if self.trace.is_some()
+            || self.profile.is_some()
+            || self.memory.observed
+            || !self.watch_hits.is_empty()
+        {
+            loop {
+                if let Some(state) = self.step()? {
+                    break Ok(state);
+                }
+            }
+        } else {
+            self.run_unobserved()
+        }
+    }
+
+    pub fn get_next_output(&mut self) -> Result<Option<i64>, IntcodeError> {
         loop {
-            let opcode = Opcode::from_memory(self.ip, &mut self.memory);
+            match self.run()? {
+                RunState::Output(value) => break Ok(Some(value)),
+                RunState::Halted => break Ok(None),
+                RunState::NeedsInput => break Err(self.input_underflow()),
+                RunState::Watchpoint(_) => (),
+            }
+        }
+    }
+
+    // steps without any of the bookkeeping traces, profiles and watchpoints need. the registers
+    // live in locals while it runs, anything left in the VM would be stored back every step
+    fn run_unobserved(&mut self) -> Result<RunState, IntcodeError> {
+        let mut registers = self.registers();
 
-            match opcode.execute(&mut self.memory, &mut self.input, self.rel_base_offset) {
-                OpcodeOutput::Halt => break None,
-                OpcodeOutput::Output(value) => {
-                    self.ip += opcode.len();
-                    break Some(value);
+        let result = loop {
+            let ip = registers.ip;
+
+            if registers.steps >= self.next_limit_check {
+                self.steps = registers.steps;
+
+                if let Err(error) = self.check_limits_now(ip) {
+                    break Err(error);
                 }
-                OpcodeOutput::Jump(ip) => {
-                    self.ip = ip;
-                    continue;
+            }
+
+            let opcode = match self.decode(ip) {
+                Ok(opcode) => opcode,
+                Err(error) => break Err(error),
+            };
+
+            match self.execute(ip, registers.rel_base_offset, opcode) {
+                Ok(output) => {
+                    if let Some(state) = registers.advance(opcode, output) {
+                        break Ok(state);
+                    }
                 }
-                OpcodeOutput::NewBaseOffset(new_base) => self.rel_base_offset = new_base,
-                OpcodeOutput::None => (),
+                Err(error) => break Err(error),
             }
+        };
+
+        self.set_registers(registers);
+        result
+    }
+
+    fn step(&mut self) -> Result<Option<RunState>, IntcodeError> {
+        if let Some(hit) = self.watch_hits.pop_front() {
+            return Ok(Some(RunState::Watchpoint(hit)));
+        }
+
+        let ip = self.ip;
+        if self.steps >= self.next_limit_check {
+            self.check_limits_now(ip)?;
+        }
+        let opcode = self.decode(ip)?;
+
+        let words = if self.trace.is_some() {
+            self.start_journal(ip, opcode.len())
+        } else {
+            Vec::new()
+        };
+
+        let output = self.execute(ip, self.rel_base_offset, opcode);
+        let journal = if self.memory.observed {
+            self.finish_observing(ip)
+        } else {
+            None
+        };
+
+        // nothing was executed, so there's nothing to count or trace either
+        let output = match output? {
+            OpcodeOutput::NeedsInput => return Ok(Some(RunState::NeedsInput)),
+            output => output,
+        };
+        let mut registers = self.registers();
+        let state = registers.advance(opcode, output);
+        self.set_registers(registers);
+
+        if self.profile.is_some() || self.trace.is_some() {
+            self.record(ip, opcode, words, journal, state);
+        }
+
+        // pause right after the instruction that tripped a watchpoint
+        Ok(state.or_else(|| self.watch_hits.pop_front().map(RunState::Watchpoint)))
+    }
+
+    #[cold]
+    fn check_limits_now(&mut self, ip: usize) -> Result<(), IntcodeError> {
+        let limit = if self.step_limit.is_some_and(|limit| self.steps >= limit) {
+            Limit::Steps
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Limit::Time
+        } else {
+            self.schedule_limit_check(self.steps + 1);
+            return Ok(());
+        };
+
+        Err(IntcodeError::BudgetExhausted {
+            ip,
+            instruction: self.memory.fetch(ip),
+            limit,
+        })
+    }
+
+    // reading the clock every step would be slower than the steps themselves, so the deadline is
+    // only checked every so often
+    fn schedule_limit_check(&mut self, from: u64) {
+        let clock = match self.deadline {
+            Some(_) => from.next_multiple_of(DEADLINE_CHECK_INTERVAL),
+            None => u64::MAX,
+        };
+
+        self.next_limit_check = self.step_limit.unwrap_or(u64::MAX).min(clock);
+    }
+
+    #[inline(always)]
+    fn decode(&mut self, ip: usize) -> Result<Opcode, IntcodeError> {
+        self.memory
+            .decode(ip, &self.extensions)
+            .map_err(|fault| self.fault(ip, fault))
+    }
+
+    #[inline(always)]
+    fn execute(
+        &mut self,
+        ip: usize,
+        rel_base_offset: i64,
+        opcode: Opcode,
+    ) -> Result<OpcodeOutput, IntcodeError> {
+        match opcode {
+            Opcode::Custom(custom) => self.extensions.execute(
+                custom,
+                &mut self.memory,
+                &mut self.input,
+                rel_base_offset,
+                ip,
+            ),
+            _ => opcode
+                .execute(&mut self.memory, &mut self.input, rel_base_offset)
+                .map_err(|fault| self.fault(ip, fault)),
+        }
+    }
+
+    fn registers(&self) -> Registers {
+        Registers {
+            ip: self.ip,
+            rel_base_offset: self.rel_base_offset,
+            steps: self.steps,
+        }
+    }
+
+    fn set_registers(&mut self, registers: Registers) {
+        self.ip = registers.ip;
+        self.rel_base_offset = registers.rel_base_offset;
+        self.steps = registers.steps;
+    }
+
+    // the instruction's words, read before it runs since it might overwrite itself
+    fn start_journal(&mut self, ip: usize, len: usize) -> Vec<i64> {
+        self.memory.journal = Some(Journal::default());
+        self.memory.observed = true;
+
+        (ip..ip + len)
+            .map(|index| self.memory.fetch(index))
+            .collect()
+    }
+
+    fn finish_observing(&mut self, ip: usize) -> Option<Journal> {
+        let journal = self.memory.journal.take();
+        self.memory.update_observed();
+
+        if self.memory.watchpoints.has_fired() {
+            self.watch_hits
+                .extend(self.memory.watchpoints.take_fired(ip));
+        }
+
+        journal
+    }
+
+    #[inline(never)]
+    fn record(
+        &mut self,
+        ip: usize,
+        opcode: Opcode,
+        words: Vec<i64>,
+        journal: Option<Journal>,
+        state: Option<RunState>,
+    ) {
+        if let Some(profile) = &mut self.profile {
+            profile.record(ip, &opcode, self.extensions.mnemonic(opcode));
+        }
+
+        if let (Some(trace), Some(journal)) = (&mut self.trace, journal) {
+            trace.record(TraceEntry {
+                ip,
+                words,
+                reads: journal.reads,
+                writes: journal.writes,
+                output: match state {
+                    Some(RunState::Output(value)) => Some(value),
+                    _ => None,
+                },
+            });
+        }
+    }
+
+    fn fault(&self, ip: usize, fault: Fault) -> IntcodeError {
+        fault.at(ip, self.memory.fetch(ip))
+    }
+
+    fn input_underflow(&self) -> IntcodeError {
+        IntcodeError::InputUnderflow {
+            ip: self.ip,
+            instruction: self.memory.fetch(self.ip),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{asm::assemble, Duration, IntcodeError, IntcodeVM, Limit, RunState};
+    use std::io::{self, Read, Write};
+
+    // fails every read
+    pub(super) struct BrokenReader;
+
+    // keeps what's written and counts the flushes, or fails every write with a different message
+    #[derive(Debug, Default)]
+    pub(super) struct Recorder {
+        pub(super) written: Vec<u8>,
+        pub(super) flushes: usize,
+        broken: bool,
+        attempts: usize,
+    }
+
+    impl Recorder {
+        pub(super) fn broken() -> Self {
+            Recorder {
+                broken: true,
+                ..Recorder::default()
+            }
+        }
+    }
+
+    impl Read for BrokenReader {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("unreadable"))
+        }
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.attempts += 1;
+
+            if self.broken {
+                Err(io::Error::other(format!("write {} failed", self.attempts)))
+            } else {
+                self.written.extend_from_slice(bytes);
+                Ok(bytes.len())
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    pub(super) fn vm(source: &str) -> IntcodeVM {
+        IntcodeVM::new(assemble(source).unwrap())
+    }
+
+    pub(super) fn outputs(vm: &mut IntcodeVM) -> Result<Vec<i64>, IntcodeError> {
+        let mut outputs = Vec::new();
 
-            self.ip += opcode.len();
+        while let Some(value) = vm.get_next_output()? {
+            outputs.push(value);
         }
+
+        Ok(outputs)
+    }
+
+    #[test]
+    fn unknown_opcode_is_an_error() {
+        let mut vm = IntcodeVM::new(vec![1101, 1, 1, 5, 42, 99]);
+
+        let error = vm.get_next_output().unwrap_err();
+        assert_eq!(
+            error,
+            IntcodeError::UnknownOpcode {
+                ip: 4,
+                instruction: 42,
+            }
+        );
+        assert_eq!(error.to_string(), "unknown opcode (instruction 42 at 4)");
+    }
+
+    #[test]
+    fn invalid_mode_is_an_error() {
+        let mut vm = IntcodeVM::new(vec![1101, 1, 1, 5, 30001, 0, 0, 0, 99]);
+
+        let error = vm.get_next_output().unwrap_err();
+        assert_eq!(
+            error,
+            IntcodeError::InvalidMode {
+                ip: 4,
+                instruction: 30001,
+                mode: 3,
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "invalid parameter mode 3 (instruction 30001 at 4)"
+        );
+    }
+
+    #[test]
+    fn write_to_immediate_is_an_error() {
+        let mut vm = IntcodeVM::new(vec![11101, 1, 1, 5, 99]);
+
+        let error = vm.get_next_output().unwrap_err();
+        assert_eq!(
+            error,
+            IntcodeError::WriteToImmediate {
+                ip: 0,
+                instruction: 11101,
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "write to an immediate mode parameter (instruction 11101 at 0)"
+        );
+    }
+
+    #[test]
+    fn missing_input_is_an_error() {
+        let mut vm = IntcodeVM::new(vec![104, 7, 3, 0, 99]);
+
+        assert_eq!(vm.get_next_output(), Ok(Some(7)));
+        let error = vm.get_next_output().unwrap_err();
+        assert_eq!(
+            error,
+            IntcodeError::InputUnderflow {
+                ip: 2,
+                instruction: 3,
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "input requested but the input queue is empty (instruction 3 at 2)"
+        );
+    }
+
+    #[test]
+    fn negative_address_is_an_error() {
+        let mut vm = IntcodeVM::new(vec![1101, 1, 1, -4, 99]);
+
+        let error = vm.get_next_output().unwrap_err();
+        assert_eq!(
+            error,
+            IntcodeError::NegativeAddress {
+                ip: 0,
+                instruction: 1101,
+                address: -4,
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "negative address -4 (instruction 1101 at 0)"
+        );
+    }
+
+    #[test]
+    fn arithmetic_overflow_wraps() {
+        let mut vm = IntcodeVM::new(vec![
+            1101,
+            i64::MAX,
+            1,
+            13,
+            1102,
+            i64::MAX,
+            2,
+            14,
+            4,
+            13,
+            4,
+            14,
+            99,
+            0,
+            0,
+        ]);
+
+        assert_eq!(outputs(&mut vm), Ok(vec![i64::MIN, -2]));
+    }
+
+    #[test]
+    fn run_pauses_for_input_and_resumes() {
+        // INPUT [9], MUL [9], #3, [9], OUTPUT [9], HALT
+        let mut vm = IntcodeVM::new(vec![3, 9, 1002, 9, 3, 9, 4, 9, 99, 0]);
+
+        assert_eq!(vm.run(), Ok(RunState::NeedsInput));
+        // asking again without input doesn't move the VM along
+        assert_eq!(vm.run(), Ok(RunState::NeedsInput));
+        assert_eq!(vm.ip, 0);
+
+        vm.input(5);
+        assert_eq!(vm.run(), Ok(RunState::Output(15)));
+        assert_eq!(vm.run(), Ok(RunState::Halted));
+    }
+
+    #[test]
+    fn run_yields_outputs_one_at_a_time() {
+        // OUTPUT #1, OUTPUT #2, ADD #1, #2, [11], OUTPUT [11], HALT
+        let mut vm = IntcodeVM::new(vec![104, 1, 104, 2, 1101, 1, 2, 11, 4, 11, 99, 0]);
+
+        assert_eq!(vm.run(), Ok(RunState::Output(1)));
+        assert_eq!(vm.ip, 2);
+        assert_eq!(vm.run(), Ok(RunState::Output(2)));
+        assert_eq!(vm.run(), Ok(RunState::Output(3)));
+        assert_eq!(vm.ip, 10);
+        assert_eq!(vm.run(), Ok(RunState::Halted));
+    }
+
+    #[test]
+    fn negative_relative_base_adjustment() {
+        let mut vm = vm("
+            RELATIVE_BASE_OFFSET #100
+            RELATIVE_BASE_OFFSET #-5
+            ADD #42, #0, rel[-3]
+            OUTPUT [92]
+            OUTPUT rel[-3]
+            HALT
+        ");
+
+        assert_eq!(outputs(&mut vm), Ok(vec![42, 42]));
+        assert_eq!(vm.rel_base_offset, 95);
+    }
+
+    #[test]
+    fn negative_relative_base() {
+        // a negative base is fine as long as the effective addresses aren't
+        let mut vm = vm("
+            RELATIVE_BASE_OFFSET #-3
+            OUTPUT rel[4]
+            HALT
+        ");
+
+        assert_eq!(outputs(&mut vm), Ok(vec![-3]));
+        assert_eq!(vm.rel_base_offset, -3);
+    }
+
+    #[test]
+    fn negative_relative_base_from_memory() {
+        let mut vm = vm("
+            RELATIVE_BASE_OFFSET #10
+            RELATIVE_BASE_OFFSET [offset]
+            OUTPUT rel[0]
+            HALT
+        offset:
+            .data -7
+        ");
+
+        // address 3 is the operand of the second instruction
+        assert_eq!(outputs(&mut vm), Ok(vec![7]));
+        assert_eq!(vm.rel_base_offset, 3);
+    }
+
+    #[test]
+    fn negative_immediates() {
+        let mut vm = vm("
+            ADD #-7, #3, [result]
+            MUL [result], #-2, [result]
+            OUTPUT [result]
+            LESS_THAN #-1, #0, [result]
+            OUTPUT [result]
+            HALT
+        result:
+            .data 0
+        ");
+
+        assert_eq!(outputs(&mut vm), Ok(vec![8, 1]));
+    }
+
+    #[test]
+    fn negative_relative_address_is_an_error() {
+        let mut vm = vm("
+            RELATIVE_BASE_OFFSET #2
+            OUTPUT rel[-3]
+            HALT
+        ");
+
+        assert_eq!(
+            vm.run(),
+            Err(IntcodeError::NegativeAddress {
+                ip: 2,
+                instruction: 204,
+                address: -1,
+            })
+        );
+    }
+
+    #[test]
+    fn negative_position_address_is_an_error() {
+        let mut vm = vm("
+            ADD #1, #1, [-4]
+            HALT
+        ");
+
+        assert_eq!(
+            vm.run(),
+            Err(IntcodeError::NegativeAddress {
+                ip: 0,
+                instruction: 1101,
+                address: -4,
+            })
+        );
+    }
+
+    #[test]
+    fn negative_jump_target_is_an_error() {
+        let mut vm = vm("JUMP_IF_TRUE #1, #-1");
+
+        assert_eq!(
+            vm.run(),
+            Err(IntcodeError::NegativeAddress {
+                ip: 0,
+                instruction: 1105,
+                address: -1,
+            })
+        );
+    }
+
+    #[test]
+    fn unused_negative_parameter_is_not_an_error() {
+        let mut vm = vm("
+            JUMP_IF_FALSE #1, [-1]
+            HALT
+        ");
+
+        assert_eq!(vm.run(), Ok(RunState::Halted));
+    }
+
+    #[test]
+    fn patched_instructions_are_decoded_again() {
+        // the loop runs long enough for the decode cache to warm up before the patch
+        let mut vm = vm("
+        loop:
+            ADD [counter], #-1, [counter]
+            JUMP_IF_TRUE [counter], #loop
+        patch:
+            OUTPUT #1
+            JUMP_IF_TRUE [done], #end
+            ADD #1, #0, [done]
+            ADD #0, #2, [patch+1]
+            JUMP_IF_TRUE #1, #patch
+        end:
+            HALT
+        counter:
+            .data 3000
+        done:
+            .data 0
+        ");
+
+        assert_eq!(outputs(&mut vm), Ok(vec![1, 2]));
+    }
+
+    #[test]
+    fn budgets_stop_infinite_loops() {
+        let mut vm = vm("
+            OUTPUT #1
+        loop:
+            JUMP_IF_TRUE #1, #loop
+        ");
+
+        vm.set_step_budget(Some(10));
+        assert_eq!(vm.run(), Ok(RunState::Output(1)));
+        assert_eq!(
+            vm.run(),
+            Err(IntcodeError::BudgetExhausted {
+                ip: 2,
+                instruction: 1105,
+                limit: Limit::Steps,
+            })
+        );
+        assert_eq!(vm.steps(), 10);
+
+        // the VM carries on where it stopped once it's given more time
+        vm.set_step_budget(None);
+        vm.set_time_limit(Some(Duration::from_millis(1)));
+        assert!(matches!(
+            vm.run(),
+            Err(IntcodeError::BudgetExhausted {
+                ip: 2,
+                limit: Limit::Time,
+                ..
+            })
+        ));
+        assert!(vm.steps() > 10);
+    }
+
+    #[test]
+    fn time_limits_past_the_end_of_the_clock_never_run_out() {
+        let mut vm = vm("
+            OUTPUT #1
+            HALT
+        ");
+
+        vm.set_time_limit(Some(Duration::MAX));
+        assert_eq!(outputs(&mut vm), Ok(vec![1]));
+    }
+
+    #[test]
+    fn memory_can_be_read_and_patched_from_outside() {
+        let mut vm = vm("
+            OUTPUT [value]
+            HALT
+        value:
+            .data 3
+        ");
+
+        assert!(vm.dump(0..5).eq([4, 3, 99, 3, 0]));
+        assert!(vm.dump(usize::MAX - 1..usize::MAX).eq([0]));
+        assert_eq!(vm.dump(2..usize::MAX).nth(1), Some(3));
+        assert_eq!(vm.read(1_000_000), 0);
+
+        vm.write(3, 8);
+        vm.write(1_000_000, 1);
+        assert_eq!(vm.read(1_000_000), 1);
+        assert!(vm.dump(2..4).eq([99, 8]));
+        assert_eq!(outputs(&mut vm), Ok(vec![8]));
+
+        // turn the halt the VM stopped at into another output
+        vm.write(2, 104);
+        vm.write(4, 99);
+        assert_eq!(outputs(&mut vm), Ok(vec![8]));
+    }
+
+    #[test]
+    fn writes_at_the_top_of_memory() {
+        let mut vm = vm("
+            RELATIVE_BASE_OFFSET #9223372036854775807
+            ADD #1, #2, rel[0]
+            OUTPUT rel[0]
+            HALT
+        ");
+
+        assert_eq!(outputs(&mut vm), Ok(vec![3]));
+        vm.write(usize::MAX, 4);
+        assert_eq!(vm.read(usize::MAX), 4);
+        assert_eq!(vm.read(i64::MAX as usize), 3);
     }
 }
@@ -0,0 +1,304 @@
+use super::{
+    io::{InputDevice, OutputDevice},
+    IntcodeError, IntcodeVM, RunState,
+};
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead, Write},
+};
+
+// output is split into runs of text and the values that aren't ASCII
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chunk {
+    Text(String),
+    Value(i64),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    chunks: Vec<Chunk>,
+}
+
+// feeds lines read from a reader as character codes
+#[derive(Debug)]
+pub struct AsciiReader<R> {
+    reader: R,
+    pending: VecDeque<i64>,
+    error: Option<io::Error>,
+}
+
+// writes ASCII output as text and anything else as a number on its own line
+#[derive(Debug)]
+pub struct AsciiWriter<W> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+fn to_ascii(value: i64) -> Option<char> {
+    if (0..=127).contains(&value) {
+        Some(value as u8 as char)
+    } else {
+        None
+    }
+}
+
+impl Transcript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    // all the text with the raw values left out
+    pub fn text(&self) -> String {
+        self.chunks
+            .iter()
+            .filter_map(|chunk| match chunk {
+                Chunk::Text(text) => Some(text.as_str()),
+                Chunk::Value(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn values(&self) -> Vec<i64> {
+        self.chunks
+            .iter()
+            .filter_map(|chunk| match chunk {
+                Chunk::Value(value) => Some(*value),
+                Chunk::Text(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+    }
+}
+
+impl OutputDevice for Transcript {
+    fn write(&mut self, value: i64) {
+        match (to_ascii(value), self.chunks.last_mut()) {
+            (Some(c), Some(Chunk::Text(text))) => text.push(c),
+            (Some(c), _) => self.chunks.push(Chunk::Text(c.to_string())),
+            (None, _) => self.chunks.push(Chunk::Value(value)),
+        }
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for chunk in &self.chunks {
+            match chunk {
+                Chunk::Text(text) => write!(f, "{}", text)?,
+                Chunk::Value(value) => writeln!(f, "{}", value)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: BufRead> AsciiReader<R> {
+    pub fn new(reader: R) -> Self {
+        AsciiReader {
+            reader,
+            pending: VecDeque::new(),
+            error: None,
+        }
+    }
+
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+}
+
+impl<R: BufRead> InputDevice for AsciiReader<R> {
+    fn read(&mut self) -> Option<i64> {
+        if self.pending.is_empty() && self.error.is_none() {
+            let mut line = String::new();
+
+            match self.reader.read_line(&mut line) {
+                Ok(0) => (),
+                Ok(_) => {
+                    let line = line.trim_end_matches(&['\r', '\n'][..]);
+                    self.pending.extend(line.bytes().map(i64::from));
+                    self.pending.push_back(i64::from(b'\n'));
+                }
+                Err(e) => self.error = Some(e),
+            }
+        }
+
+        self.pending.pop_front()
+    }
+}
+
+impl<W: Write> AsciiWriter<W> {
+    pub fn new(writer: W) -> Self {
+        AsciiWriter {
+            writer,
+            error: None,
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.error {
+            Some(e) => Err(e),
+            None => {
+                self.writer.flush()?;
+                Ok(self.writer)
+            }
+        }
+    }
+}
+
+impl<W: Write> OutputDevice for AsciiWriter<W> {
+    fn write(&mut self, value: i64) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let result = match to_ascii(value) {
+            // flush on newlines so prompts show up before the program waits for input
+            Some('\n') => writeln!(self.writer).and_then(|_| self.writer.flush()),
+            Some(c) => write!(self.writer, "{}", c),
+            None => writeln!(self.writer, "{}", value),
+        };
+
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+    }
+}
+
+impl IntcodeVM {
+    pub fn input_text(&mut self, text: &str) {
+        for byte in text.bytes() {
+            self.input(i64::from(byte));
+        }
+    }
+
+    // the line is terminated with a newline
+    pub fn input_line(&mut self, line: &str) {
+        self.input_text(line);
+        self.input(i64::from(b'\n'));
+    }
+
+    // runs until the program halts or waits for input, collecting everything it outputs.
+    // watchpoints are skipped over
+    pub fn run_ascii(&mut self) -> Result<(Transcript, RunState), IntcodeError> {
+        let mut transcript = Transcript::new();
+
+        loop {
+            match self.run_with(&mut || None, &mut transcript)? {
+                RunState::Watchpoint(_) => (),
+                state => break Ok((transcript, state)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            tests::{vm, BrokenReader, Recorder},
+            RunState,
+        },
+        AsciiReader, AsciiWriter, InputDevice, OutputDevice,
+    };
+    use std::io::{BufReader, Cursor};
+
+    fn codes(text: &str) -> Vec<i64> {
+        text.bytes().map(i64::from).collect()
+    }
+
+    #[test]
+    fn reader_feeds_lines_without_carriage_returns() {
+        let mut reader = AsciiReader::new(Cursor::new("go\r\nnorth\nlast"));
+        let read: Vec<_> = std::iter::from_fn(|| reader.read()).collect();
+
+        // the last line gets a newline even though the input doesn't end with one
+        assert_eq!(read, codes("go\nnorth\nlast\n"));
+        assert_eq!(reader.read(), None);
+        assert!(reader.error().is_none());
+    }
+
+    #[test]
+    fn reader_keeps_read_errors() {
+        let mut reader = AsciiReader::new(BufReader::new(BrokenReader));
+
+        assert_eq!(reader.read(), None);
+        assert_eq!(reader.error().unwrap().to_string(), "unreadable");
+        assert_eq!(reader.read(), None);
+    }
+
+    #[test]
+    fn writer_puts_raw_values_on_their_own_line() {
+        let mut writer = AsciiWriter::new(Vec::new());
+
+        for value in codes("hi\n").into_iter().chain(vec![1000, -1]) {
+            writer.write(value);
+        }
+
+        assert_eq!(writer.finish().unwrap(), b"hi\n1000\n-1\n");
+    }
+
+    #[test]
+    fn writer_flushes_on_newlines() {
+        let mut writer = AsciiWriter::new(Recorder::default());
+
+        writer.write(i64::from(b'>'));
+        assert_eq!(writer.writer.flushes, 0);
+        writer.write(i64::from(b'\n'));
+        assert_eq!(writer.writer.flushes, 1);
+
+        let recorder = writer.finish().unwrap();
+        assert_eq!(recorder.written, b">\n");
+        assert_eq!(recorder.flushes, 2);
+    }
+
+    #[test]
+    fn writer_finish_returns_the_first_error() {
+        let mut writer = AsciiWriter::new(Recorder::broken());
+        writer.write(i64::from(b'a'));
+        writer.write(i64::from(b'b'));
+
+        assert_eq!(writer.finish().unwrap_err().to_string(), "write 1 failed");
+    }
+
+    #[test]
+    fn ascii_lines_and_raw_values() {
+        let mut vm = vm("
+        loop:
+            INPUT [char]
+            OUTPUT [char]
+            EQUALS [char], #10, [newline]
+            JUMP_IF_FALSE [newline], #loop
+            OUTPUT #1000
+            OUTPUT #62
+            HALT
+        char:
+            .data 0
+        newline:
+            .data 0
+        ");
+
+        let (transcript, state) = vm.run_ascii().unwrap();
+        assert!(transcript.is_empty());
+        assert_eq!(state, RunState::NeedsInput);
+
+        vm.input_line("hi");
+        let (transcript, state) = vm.run_ascii().unwrap();
+        assert_eq!(transcript.text(), "hi\n>");
+        assert_eq!(transcript.values(), vec![1000]);
+        assert_eq!(transcript.to_string(), "hi\n1000\n>");
+        assert_eq!(state, RunState::Halted);
+    }
+}
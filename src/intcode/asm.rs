@@ -0,0 +1,460 @@
+use super::Opcode;
+use std::{collections::HashMap, error::Error, fmt};
+
+const OPCODES: [i64; 10] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 99];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssembleError {
+    UnknownMnemonic {
+        line: usize,
+        mnemonic: String,
+    },
+    OperandCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    InvalidOperand {
+        line: usize,
+        operand: String,
+    },
+    InvalidLabel {
+        line: usize,
+        label: String,
+    },
+    DuplicateLabel {
+        line: usize,
+        label: String,
+    },
+    UndefinedLabel {
+        line: usize,
+        label: String,
+    },
+    AddressOverflow {
+        line: usize,
+        label: String,
+    },
+}
+
+#[derive(Debug)]
+enum Expr {
+    Number(i64),
+    Label { name: String, offset: i64 },
+}
+
+#[derive(Debug)]
+enum Statement {
+    Instruction {
+        opcode: i64,
+        operands: Vec<(i64, Expr)>,
+    },
+    Data(Vec<Expr>),
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownMnemonic { line, mnemonic } => {
+                write!(f, "line {}: unknown mnemonic {}", line, mnemonic)
+            }
+            Self::OperandCount {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected {} operands, found {}",
+                line, expected, found
+            ),
+            Self::InvalidOperand { line, operand } => {
+                write!(f, "line {}: invalid operand '{}'", line, operand)
+            }
+            Self::InvalidLabel { line, label } => {
+                write!(f, "line {}: invalid label '{}'", line, label)
+            }
+            Self::DuplicateLabel { line, label } => {
+                write!(f, "line {}: label {} is already defined", line, label)
+            }
+            Self::UndefinedLabel { line, label } => {
+                write!(f, "line {}: label {} is not defined", line, label)
+            }
+            Self::AddressOverflow { line, label } => write!(
+                f,
+                "line {}: offset from label {} is out of range",
+                line, label
+            ),
+        }
+    }
+}
+
+impl Error for AssembleError {}
+
+impl Statement {
+    fn len(&self) -> usize {
+        match self {
+            Self::Instruction { operands, .. } => 1 + operands.len(),
+            Self::Data(values) => values.len(),
+        }
+    }
+}
+
+impl Expr {
+    fn resolve(&self, line: usize, labels: &HashMap<String, usize>) -> Result<i64, AssembleError> {
+        match self {
+            Self::Number(value) => Ok(*value),
+            Self::Label { name, offset } => {
+                let address = labels
+                    .get(name)
+                    .ok_or_else(|| AssembleError::UndefinedLabel {
+                        line,
+                        label: name.clone(),
+                    })?;
+
+                (*address as i64).checked_add(*offset).ok_or_else(|| {
+                    AssembleError::AddressOverflow {
+                        line,
+                        label: name.clone(),
+                    }
+                })
+            }
+        }
+    }
+}
+
+fn normalize(mnemonic: &str) -> String {
+    mnemonic
+        .chars()
+        .filter(|&c| c != '_')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// returns the opcode number and how many parameters it takes. the mnemonics are looked up from
+// the decoder so they always match what the disassembler prints
+fn lookup(mnemonic: &str) -> Option<(i64, usize)> {
+    let wanted = normalize(mnemonic);
+
+    OPCODES.iter().find_map(|&number| {
+        let opcode = Opcode::decode(0, |index| if index == 0 { number } else { 0 }).ok()?;
+
+        if normalize(opcode.mnemonic()) == wanted {
+            Some((number, opcode.len() - 1))
+        } else {
+            None
+        }
+    })
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn parse_expr(line: usize, text: &str) -> Result<Expr, AssembleError> {
+    let text = text.trim();
+    let invalid = || AssembleError::InvalidOperand {
+        line,
+        operand: text.to_string(),
+    };
+
+    if let Ok(value) = text.parse() {
+        return Ok(Expr::Number(value));
+    }
+
+    // label, label+offset or label-offset
+    let (name, offset) = match text.find(&['+', '-'][..]) {
+        Some(split) => {
+            let name = text[..split].trim();
+            let offset: i64 = text[split + 1..].trim().parse().map_err(|_| invalid())?;
+            let offset = if text[split..].starts_with('-') {
+                // the most negative offset has no negation that fits
+                offset
+                    .checked_neg()
+                    .ok_or_else(|| AssembleError::AddressOverflow {
+                        line,
+                        label: name.to_string(),
+                    })?
+            } else {
+                offset
+            };
+            (name, offset)
+        }
+        None => (text, 0),
+    };
+
+    if is_identifier(name) {
+        Ok(Expr::Label {
+            name: name.to_string(),
+            offset,
+        })
+    } else {
+        Err(invalid())
+    }
+}
+
+fn parse_operand(line: usize, text: &str) -> Result<(i64, Expr), AssembleError> {
+    let text = text.trim();
+
+    let (mode, inner) = if let Some(inner) = text.strip_prefix('#') {
+        (1, inner)
+    } else if let Some(inner) = text.strip_prefix("rel[").and_then(|t| t.strip_suffix(']')) {
+        (2, inner)
+    } else if let Some(inner) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        (0, inner)
+    } else {
+        return Err(AssembleError::InvalidOperand {
+            line,
+            operand: text.to_string(),
+        });
+    };
+
+    Ok((mode, parse_expr(line, inner)?))
+}
+
+fn split_operands(text: &str) -> Vec<&str> {
+    if text.trim().is_empty() {
+        Vec::new()
+    } else {
+        text.split(',').collect()
+    }
+}
+
+fn parse_statement(line: usize, text: &str) -> Result<Statement, AssembleError> {
+    let (head, rest) = match text.find(char::is_whitespace) {
+        Some(split) => (&text[..split], &text[split..]),
+        None => (text, ""),
+    };
+    let operands = split_operands(rest);
+
+    if head.eq_ignore_ascii_case(".data") {
+        if operands.is_empty() {
+            return Err(AssembleError::InvalidOperand {
+                line,
+                operand: String::new(),
+            });
+        }
+
+        let values = operands
+            .into_iter()
+            .map(|operand| parse_expr(line, operand))
+            .collect::<Result<_, _>>()?;
+        return Ok(Statement::Data(values));
+    }
+
+    let (opcode, expected) = lookup(head).ok_or_else(|| AssembleError::UnknownMnemonic {
+        line,
+        mnemonic: head.to_string(),
+    })?;
+
+    if operands.len() != expected {
+        return Err(AssembleError::OperandCount {
+            line,
+            expected,
+            found: operands.len(),
+        });
+    }
+
+    let operands = operands
+        .into_iter()
+        .map(|operand| parse_operand(line, operand))
+        .collect::<Result<_, _>>()?;
+    Ok(Statement::Instruction { opcode, operands })
+}
+
+pub fn assemble(source: &str) -> Result<Vec<i64>, AssembleError> {
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = 0;
+
+    // first pass: parse every statement and figure out where the labels point to
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let mut text = line.split(';').next().unwrap_or("").trim();
+
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+
+            if !is_identifier(label) {
+                return Err(AssembleError::InvalidLabel {
+                    line: line_number,
+                    label: label.to_string(),
+                });
+            }
+
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(AssembleError::DuplicateLabel {
+                    line: line_number,
+                    label: label.to_string(),
+                });
+            }
+
+            text = text[colon + 1..].trim();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let statement = parse_statement(line_number, text)?;
+        address += statement.len();
+        statements.push((line_number, statement));
+    }
+
+    // second pass: resolve the labels and encode
+    let mut program = Vec::with_capacity(address);
+
+    for (line, statement) in statements {
+        match statement {
+            Statement::Instruction { opcode, operands } => {
+                let modes = operands
+                    .iter()
+                    .zip(&[100, 1000, 10_000])
+                    .map(|((mode, _), place)| mode * place)
+                    .sum::<i64>();
+                program.push(opcode + modes);
+
+                for (_, expr) in operands {
+                    program.push(expr.resolve(line, &labels)?);
+                }
+            }
+            Statement::Data(values) => {
+                for expr in values {
+                    program.push(expr.resolve(line, &labels)?);
+                }
+            }
+        }
+    }
+
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, AssembleError};
+
+    #[test]
+    fn labels_and_offsets_resolve_to_addresses() {
+        let program = assemble(
+            "
+        start:
+            ADD [value], #1, [value+1] ; comments are ignored
+            JUMP_IF_TRUE #1, #start
+        value: .data 5, value-1, end
+        end:
+        ",
+        )
+        .unwrap();
+
+        assert_eq!(program, vec![1001, 7, 1, 8, 1105, 1, 0, 5, 6, 10]);
+    }
+
+    #[test]
+    fn operand_modes_are_encoded() {
+        assert_eq!(
+            assemble("LESS_THAN #-3, rel[2], [0]"),
+            Ok(vec![2107, -3, 2, 0])
+        );
+        // mnemonics don't care about case or underscores
+        assert_eq!(assemble("relativebaseoffset rel[-1]"), Ok(vec![209, -1]));
+        assert_eq!(assemble("halt"), Ok(vec![99]));
+    }
+
+    #[test]
+    fn labels_have_to_be_defined_once() {
+        assert_eq!(
+            assemble("JUMP_IF_TRUE #1, #nowhere"),
+            Err(AssembleError::UndefinedLabel {
+                line: 1,
+                label: String::from("nowhere"),
+            })
+        );
+        assert_eq!(
+            assemble("a: HALT\na: HALT"),
+            Err(AssembleError::DuplicateLabel {
+                line: 2,
+                label: String::from("a"),
+            })
+        );
+        assert_eq!(
+            assemble("1a: HALT"),
+            Err(AssembleError::InvalidLabel {
+                line: 1,
+                label: String::from("1a"),
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_statements_are_an_error() {
+        assert_eq!(
+            assemble("HALT\nJUMP #0"),
+            Err(AssembleError::UnknownMnemonic {
+                line: 2,
+                mnemonic: String::from("JUMP"),
+            })
+        );
+        assert_eq!(
+            assemble("ADD #1, #2"),
+            Err(AssembleError::OperandCount {
+                line: 1,
+                expected: 3,
+                found: 2,
+            })
+        );
+        assert_eq!(
+            assemble("OUTPUT 5"),
+            Err(AssembleError::InvalidOperand {
+                line: 1,
+                operand: String::from("5"),
+            })
+        );
+        assert_eq!(
+            assemble("OUTPUT [x+y]"),
+            Err(AssembleError::InvalidOperand {
+                line: 1,
+                operand: String::from("x+y"),
+            })
+        );
+        assert_eq!(
+            assemble(".data"),
+            Err(AssembleError::InvalidOperand {
+                line: 1,
+                operand: String::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn offsets_out_of_range_are_an_error() {
+        let error = assemble("HALT\nlast: .data last+9223372036854775807").unwrap_err();
+
+        assert_eq!(
+            error,
+            AssembleError::AddressOverflow {
+                line: 2,
+                label: String::from("last"),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "line 2: offset from label last is out of range"
+        );
+    }
+
+    #[test]
+    fn offsets_that_cant_be_negated_are_an_error() {
+        assert_eq!(
+            assemble("x: HALT\n.data x--9223372036854775808"),
+            Err(AssembleError::AddressOverflow {
+                line: 2,
+                label: String::from("x"),
+            })
+        );
+    }
+}
@@ -0,0 +1,275 @@
+use super::{disasm::decode_at, Opcode, Parameter};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+// how a block is left. jump targets are None when they aren't immediates, like returns through a
+// stored address, since those can't be followed statically
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminator {
+    // the next block starts right after this one
+    Fallthrough(usize),
+    Branch {
+        taken: Option<usize>,
+        not_taken: usize,
+    },
+    Jump(Option<usize>),
+    Halt,
+    // the code runs into something that doesn't decode or off the end of the program
+    Invalid,
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub start: usize,
+    pub end: usize,
+    pub terminator: Terminator,
+    instructions: Vec<(usize, Opcode)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    blocks: BTreeMap<usize, Block>,
+}
+
+// a conditional jump that always or never jumps is treated as a plain jump or no jump at all
+pub(super) fn jump(opcode: Opcode) -> Option<(Option<bool>, Option<usize>)> {
+    let (condition, target, jump_if) = match opcode {
+        Opcode::JumpIfTrue(condition, target) => (condition, target, true),
+        Opcode::JumpIfFalse(condition, target) => (condition, target, false),
+        _ => return None,
+    };
+
+    let always = match condition {
+        Parameter::Immediate(value) => Some((value != 0) == jump_if),
+        _ => None,
+    };
+    let target = match target {
+        Parameter::Immediate(value) if value >= 0 => Some(value as usize),
+        _ => None,
+    };
+
+    Some((always, target))
+}
+
+// the addresses execution can continue at after the instruction
+pub(super) fn successors(address: usize, opcode: Opcode) -> Vec<usize> {
+    let next = address + opcode.len();
+
+    match (opcode, jump(opcode)) {
+        (Opcode::Halt, _) => Vec::new(),
+        (_, Some((Some(true), target))) => target.into_iter().collect(),
+        (_, Some((Some(false), _))) => vec![next],
+        (_, Some((None, target))) => target.into_iter().chain(Some(next)).collect(),
+        (_, None) => vec![next],
+    }
+}
+
+impl Block {
+    pub fn successors(&self) -> Vec<Option<usize>> {
+        match self.terminator {
+            Terminator::Fallthrough(next) => vec![Some(next)],
+            Terminator::Branch { taken, not_taken } => vec![taken, Some(not_taken)],
+            Terminator::Jump(target) => vec![target],
+            Terminator::Halt | Terminator::Invalid => Vec::new(),
+        }
+    }
+
+    // the block's instructions as disassembly lines
+    pub fn listing(&self) -> Vec<String> {
+        self.instructions
+            .iter()
+            .map(|(address, opcode)| format!("{:04}: {}", address, opcode))
+            .collect()
+    }
+}
+
+impl Cfg {
+    // follows every path from address 0 that can be followed without running the program, so
+    // code only reached through computed jumps or written at runtime isn't included
+    pub fn build(program: &[i64]) -> Self {
+        let mut decoded = BTreeMap::new();
+        let mut leaders = BTreeSet::new();
+        let mut pending = vec![0];
+        leaders.insert(0);
+
+        while let Some(address) = pending.pop() {
+            if decoded.contains_key(&address) {
+                continue;
+            }
+
+            let opcode = decode_at(program, address);
+            decoded.insert(address, opcode);
+
+            if let Some(opcode) = opcode {
+                let next = successors(address, opcode);
+
+                if jump(opcode).is_some() {
+                    leaders.extend(next.iter().copied());
+                }
+
+                pending.extend(next);
+            }
+        }
+
+        let blocks = leaders
+            .iter()
+            .map(|&start| (start, Self::build_block(start, &decoded, &leaders)))
+            .collect();
+
+        Cfg { blocks }
+    }
+
+    fn build_block(
+        start: usize,
+        decoded: &BTreeMap<usize, Option<Opcode>>,
+        leaders: &BTreeSet<usize>,
+    ) -> Block {
+        let mut instructions = Vec::new();
+        let mut address = start;
+
+        let terminator = loop {
+            let opcode = match decoded.get(&address).copied().flatten() {
+                Some(opcode) => opcode,
+                None => break Terminator::Invalid,
+            };
+
+            instructions.push((address, opcode));
+            address += opcode.len();
+
+            match (opcode, jump(opcode)) {
+                (Opcode::Halt, _) => break Terminator::Halt,
+                (_, Some((Some(true), target))) => break Terminator::Jump(target),
+                (_, Some((Some(false), _))) => break Terminator::Fallthrough(address),
+                (_, Some((None, taken))) => {
+                    break Terminator::Branch {
+                        taken,
+                        not_taken: address,
+                    }
+                }
+                (_, None) if leaders.contains(&address) => break Terminator::Fallthrough(address),
+                (_, None) => (),
+            }
+        };
+
+        Block {
+            start,
+            end: address,
+            terminator,
+            instructions,
+        }
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    pub fn block(&self, start: usize) -> Option<&Block> {
+        self.blocks.get(&start)
+    }
+
+    // blocks are labeled with their disassembly, and jumps that can't be followed all point to a
+    // single node marked with a question mark
+    pub fn to_dot(&self) -> String {
+        let mut dot =
+            String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+        let mut unknown = false;
+
+        for block in self.blocks() {
+            let mut label: String = block
+                .listing()
+                .iter()
+                .map(|line| format!("{}\\l", line))
+                .collect();
+
+            match block.terminator {
+                Terminator::Halt => label.push_str("(halt)\\l"),
+                Terminator::Invalid => label.push_str("(invalid)\\l"),
+                _ => (),
+            }
+
+            writeln!(dot, "    b{} [label=\"{}\"];", block.start, label).unwrap();
+
+            let edges: Vec<_> = match block.terminator {
+                Terminator::Branch { taken, not_taken } => {
+                    vec![(taken, "taken"), (Some(not_taken), "not taken")]
+                }
+                _ => block.successors().into_iter().map(|to| (to, "")).collect(),
+            };
+
+            for (to, kind) in edges {
+                let to = match to {
+                    Some(to) => format!("b{}", to),
+                    None => {
+                        unknown = true;
+                        String::from("unknown")
+                    }
+                };
+
+                if kind.is_empty() {
+                    writeln!(dot, "    b{} -> {};", block.start, to).unwrap();
+                } else {
+                    writeln!(dot, "    b{} -> {} [label=\"{}\"];", block.start, to, kind).unwrap();
+                }
+            }
+        }
+
+        if unknown {
+            dot.push_str("    unknown [label=\"?\", shape=ellipse];\n");
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::asm::assemble, Cfg, Terminator};
+
+    #[test]
+    fn cfg_splits_blocks_at_jumps() {
+        let program = assemble(
+            "
+            INPUT [value]
+            JUMP_IF_FALSE [value], #zero
+            OUTPUT #1
+            JUMP_IF_TRUE #1, [return]
+        zero:
+            OUTPUT #0
+            HALT
+        value:
+            .data 0
+        return:
+            .data 0
+        ",
+        )
+        .unwrap();
+
+        let cfg = Cfg::build(&program);
+        let blocks: Vec<_> = cfg
+            .blocks()
+            .map(|block| (block.start, block.end, block.terminator))
+            .collect();
+        assert_eq!(
+            blocks,
+            vec![
+                (
+                    0,
+                    5,
+                    Terminator::Branch {
+                        taken: Some(10),
+                        not_taken: 5
+                    }
+                ),
+                (5, 10, Terminator::Jump(None)),
+                (10, 13, Terminator::Halt),
+            ]
+        );
+
+        let dot = cfg.to_dot();
+        assert!(dot.contains("b0 -> b10 [label=\"taken\"];"));
+        assert!(dot.contains("b5 -> unknown;"));
+    }
+}
@@ -0,0 +1,1074 @@
+use super::{
+    address,
+    io::{InputDevice, OutputDevice},
+    memory::{MemoryBackend, SparseMemory},
+    Fault, IntcodeError, Limit, Opcode, Parameter, RunState,
+};
+use std::collections::VecDeque;
+
+// long straight runs are split so a block never gets too expensive to throw away
+const MAX_BLOCK_LEN: usize = 64;
+// code is only compiled once execution has reached it this many times. anything run less often
+// is quicker to interpret than to compile
+const COMPILE_THRESHOLD: u8 = 4;
+// only code below this is compiled, so the table of blocks and the record of which words are
+// compiled stay small. anything past it is interpreted
+const COMPILE_LIMIT: usize = 1 << 16;
+
+// runs programs by compiling each basic block into a list of closures once execution has reached
+// it COMPILE_THRESHOLD times. code that runs less often than that, code past COMPILE_LIMIT, and
+// words a program writes over after they've been compiled, are run by decoding them every time
+// instead, the way the interpreter does.
+//
+// it takes input and gives output the same way IntcodeVM does, through input, run, run_with,
+// get_next_output and as a device, and counts steps and step budgets the same way. that's all it
+// shares: watchpoints, traces, profiles, time limits, snapshots and registered opcodes are only on
+// IntcodeVM. it only pays off on long runs, see benches/intcode.rs
+pub struct CompiledVM {
+    machine: Machine,
+    ip: usize,
+    blocks: Vec<Block>,
+    // where in blocks the block starting at each address is
+    starts: Vec<Option<usize>>,
+    // how many times execution has reached each address that doesn't start a block, up to
+    // COMPILE_THRESHOLD
+    visits: Vec<u8>,
+    steps: u64,
+    // the step count the budget runs out at
+    step_limit: Option<u64>,
+    // what stopped another VM reading from this one as an input device
+    device_error: Option<IntcodeError>,
+}
+
+struct Machine {
+    // every word below COMPILE_LIMIT, grown as far as the highest one written. that's where
+    // programs keep their stacks, so nearly every access is a plain index
+    near: Vec<i64>,
+    // everything past it
+    far: SparseMemory,
+    input: VecDeque<i64>,
+    rel_base_offset: i64,
+    // words that are part of a compiled block
+    code: Vec<bool>,
+    // words that have been written over after being compiled
+    patched: Vec<bool>,
+    modified: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Immediate(i64),
+    Position(usize),
+    Relative(i64),
+    // a negative position, which fails when it's used
+    Invalid(i64),
+}
+
+enum Flow {
+    Next,
+    Jump(usize),
+    NeedsInput,
+    Output(i64),
+    Halt,
+}
+
+type Step = Box<dyn Fn(&mut Machine) -> Result<Flow, Fault>>;
+
+struct Block {
+    // the address and length of each instruction, and the closure running it
+    steps: Vec<(usize, usize, Step)>,
+    // the address and index of the block that ran after this one the last time it fell through,
+    // and the last time it jumped, so going round a loop doesn't need starts
+    successors: [Option<(usize, usize)>; 2],
+}
+
+// how a block stopped: how many of its instructions finished before the last one it started,
+// where that one is, how long it is, and what it did
+type BlockExit = (u64, usize, usize, Result<Flow, Fault>);
+
+// an operand of a kind that's known when the instruction is compiled, so the closure running it
+// doesn't have to look
+trait Source: Copy + 'static {
+    fn load(self, machine: &Machine) -> Result<i64, Fault>;
+}
+
+trait Destination: Copy + 'static {
+    fn position(self, machine: &Machine) -> Result<usize, Fault>;
+}
+
+#[derive(Clone, Copy)]
+struct Immediate(i64);
+
+#[derive(Clone, Copy)]
+struct Position(usize);
+
+#[derive(Clone, Copy)]
+struct Relative(i64);
+
+impl Operand {
+    fn new(parameter: Parameter) -> Self {
+        match parameter {
+            Parameter::Immediate(value) => Self::Immediate(value),
+            Parameter::Position(value) if value < 0 => Self::Invalid(value),
+            Parameter::Position(value) => Self::Position(value as usize),
+            Parameter::Relative(offset) => Self::Relative(offset),
+        }
+    }
+}
+
+impl Source for Immediate {
+    fn load(self, _: &Machine) -> Result<i64, Fault> {
+        Ok(self.0)
+    }
+}
+
+impl Source for Position {
+    fn load(self, machine: &Machine) -> Result<i64, Fault> {
+        Ok(machine.load(self.0))
+    }
+}
+
+impl Source for Relative {
+    fn load(self, machine: &Machine) -> Result<i64, Fault> {
+        Ok(machine.load(self.position(machine)?))
+    }
+}
+
+// operands of any kind, for decoded instructions
+impl Source for Operand {
+    fn load(self, machine: &Machine) -> Result<i64, Fault> {
+        match self {
+            Operand::Immediate(value) => Ok(value),
+            _ => Ok(machine.load(self.position(machine)?)),
+        }
+    }
+}
+
+impl Destination for Position {
+    fn position(self, _: &Machine) -> Result<usize, Fault> {
+        Ok(self.0)
+    }
+}
+
+impl Destination for Relative {
+    fn position(self, machine: &Machine) -> Result<usize, Fault> {
+        address(machine.rel_base_offset.saturating_add(self.0))
+    }
+}
+
+impl Destination for Operand {
+    fn position(self, machine: &Machine) -> Result<usize, Fault> {
+        match self {
+            Operand::Position(index) => Ok(index),
+            Operand::Relative(offset) => Relative(offset).position(machine),
+            Operand::Invalid(value) => Err(Fault::NegativeAddress(value)),
+            Operand::Immediate(_) => Err(Fault::WriteToImmediate),
+        }
+    }
+}
+
+impl Machine {
+    fn new(mut memory: Vec<i64>) -> Self {
+        let mut far = SparseMemory::new(Vec::new());
+
+        if memory.len() > COMPILE_LIMIT {
+            for (index, value) in memory.drain(COMPILE_LIMIT..).enumerate() {
+                far.set(COMPILE_LIMIT + index, value);
+            }
+        }
+
+        Machine {
+            near: memory,
+            far,
+            input: VecDeque::new(),
+            rel_base_offset: 0,
+            code: Vec::new(),
+            patched: Vec::new(),
+            modified: false,
+        }
+    }
+
+    #[inline(always)]
+    fn load(&self, index: usize) -> i64 {
+        match self.near.get(index) {
+            Some(&value) => value,
+            None if index < COMPILE_LIMIT => 0,
+            None => self.far.get(index),
+        }
+    }
+
+    fn store(&mut self, index: usize, value: i64) {
+        if index < COMPILE_LIMIT {
+            if self.near.len() <= index {
+                self.near.resize(index + 1, 0);
+            }
+
+            self.near[index] = value;
+        } else {
+            self.far.set(index, value);
+        }
+
+        if self.code.get(index).copied().unwrap_or(false) {
+            self.patched[index] = true;
+            self.modified = true;
+        }
+    }
+
+    fn is_patched(&self, index: usize) -> bool {
+        self.patched.get(index).copied().unwrap_or(false)
+    }
+}
+
+impl Machine {
+    fn arithmetic<A, B, D, F>(&mut self, a: A, b: B, dest: D, f: F) -> Result<Flow, Fault>
+    where
+        A: Source,
+        B: Source,
+        D: Destination,
+        F: Fn(i64, i64) -> i64,
+    {
+        let value = f(a.load(self)?, b.load(self)?);
+        self.store(dest.position(self)?, value);
+        Ok(Flow::Next)
+    }
+
+    fn take_input<D: Destination>(&mut self, dest: D) -> Result<Flow, Fault> {
+        let index = dest.position(self)?;
+
+        match self.input.pop_front() {
+            Some(value) => {
+                self.store(index, value);
+                Ok(Flow::Next)
+            }
+            None => Ok(Flow::NeedsInput),
+        }
+    }
+
+    fn output<S: Source>(&mut self, value: S) -> Result<Flow, Fault> {
+        Ok(Flow::Output(value.load(self)?))
+    }
+
+    fn jump_if<C, T>(&mut self, condition: C, target: T, when: bool) -> Result<Flow, Fault>
+    where
+        C: Source,
+        T: Source,
+    {
+        if (condition.load(self)? != 0) == when {
+            Ok(Flow::Jump(address(target.load(self)?)?))
+        } else {
+            Ok(Flow::Next)
+        }
+    }
+
+    fn offset<S: Source>(&mut self, offset: S) -> Result<Flow, Fault> {
+        self.rel_base_offset = self.rel_base_offset.saturating_add(offset.load(self)?);
+        Ok(Flow::Next)
+    }
+
+    // runs an instruction straight from its decoded form, for code that isn't compiled
+    fn execute(&mut self, opcode: Opcode) -> Result<Flow, Fault> {
+        match opcode {
+            Opcode::Add(a, b, dest) => {
+                self.arithmetic(Operand::new(a), Operand::new(b), Operand::new(dest), add)
+            }
+            Opcode::Mul(a, b, dest) => {
+                self.arithmetic(Operand::new(a), Operand::new(b), Operand::new(dest), mul)
+            }
+            Opcode::LessThan(a, b, dest) => self.arithmetic(
+                Operand::new(a),
+                Operand::new(b),
+                Operand::new(dest),
+                less_than,
+            ),
+            Opcode::Equals(a, b, dest) => {
+                self.arithmetic(Operand::new(a), Operand::new(b), Operand::new(dest), equals)
+            }
+            Opcode::Input(dest) => self.take_input(Operand::new(dest)),
+            Opcode::Output(value) => self.output(Operand::new(value)),
+            Opcode::JumpIfTrue(condition, target) => {
+                self.jump_if(Operand::new(condition), Operand::new(target), true)
+            }
+            Opcode::JumpIfFalse(condition, target) => {
+                self.jump_if(Operand::new(condition), Operand::new(target), false)
+            }
+            Opcode::RelativeBaseOffset(offset) => self.offset(Operand::new(offset)),
+            Opcode::Halt => Ok(Flow::Halt),
+            // registered opcodes only exist on IntcodeVM, nothing here decodes to them
+            Opcode::Custom(_) => Err(Fault::UnknownOpcode),
+        }
+    }
+}
+
+fn add(a: i64, b: i64) -> i64 {
+    a.wrapping_add(b)
+}
+
+fn mul(a: i64, b: i64) -> i64 {
+    a.wrapping_mul(b)
+}
+
+fn less_than(a: i64, b: i64) -> i64 {
+    (a < b) as i64
+}
+
+fn equals(a: i64, b: i64) -> i64 {
+    (a == b) as i64
+}
+
+// a jump that isn't taken carries on with the rest of the block, so only jumps that always are end
+// one
+fn ends_block(opcode: Opcode) -> bool {
+    match opcode {
+        Opcode::JumpIfTrue(Parameter::Immediate(condition), _) => condition != 0,
+        Opcode::JumpIfFalse(Parameter::Immediate(condition), _) => condition == 0,
+        Opcode::Output(_) | Opcode::Halt => true,
+        _ => false,
+    }
+}
+
+// the same semantics as Machine::execute, with a closure built for the kind of instruction and the
+// kinds of its operands. negative positions and writes to immediates only ever fault, so those are
+// left to execute
+fn compile_step(opcode: Opcode) -> Step {
+    match opcode {
+        Opcode::Add(a, b, dest) => arithmetic_step(opcode, a, b, dest, add),
+        Opcode::Mul(a, b, dest) => arithmetic_step(opcode, a, b, dest, mul),
+        Opcode::LessThan(a, b, dest) => arithmetic_step(opcode, a, b, dest, less_than),
+        Opcode::Equals(a, b, dest) => arithmetic_step(opcode, a, b, dest, equals),
+        Opcode::Input(dest) => match Operand::new(dest) {
+            Operand::Position(index) => Box::new(move |m| m.take_input(Position(index))),
+            Operand::Relative(offset) => Box::new(move |m| m.take_input(Relative(offset))),
+            _ => decoded_step(opcode),
+        },
+        Opcode::Output(value) => match Operand::new(value) {
+            Operand::Immediate(value) => Box::new(move |m| m.output(Immediate(value))),
+            Operand::Position(index) => Box::new(move |m| m.output(Position(index))),
+            Operand::Relative(offset) => Box::new(move |m| m.output(Relative(offset))),
+            Operand::Invalid(_) => decoded_step(opcode),
+        },
+        Opcode::JumpIfTrue(condition, target) => jump_step(opcode, condition, target, true),
+        Opcode::JumpIfFalse(condition, target) => jump_step(opcode, condition, target, false),
+        Opcode::RelativeBaseOffset(offset) => match Operand::new(offset) {
+            Operand::Immediate(value) => Box::new(move |m| m.offset(Immediate(value))),
+            Operand::Position(index) => Box::new(move |m| m.offset(Position(index))),
+            Operand::Relative(offset) => Box::new(move |m| m.offset(Relative(offset))),
+            Operand::Invalid(_) => decoded_step(opcode),
+        },
+        Opcode::Halt => Box::new(|_| Ok(Flow::Halt)),
+        Opcode::Custom(_) => decoded_step(opcode),
+    }
+}
+
+fn decoded_step(opcode: Opcode) -> Step {
+    Box::new(move |m| m.execute(opcode))
+}
+
+fn arithmetic_step<F>(opcode: Opcode, a: Parameter, b: Parameter, dest: Parameter, f: F) -> Step
+where
+    F: Fn(i64, i64) -> i64 + Copy + 'static,
+{
+    fn with_b<A, F>(opcode: Opcode, a: A, b: Parameter, dest: Parameter, f: F) -> Step
+    where
+        A: Source,
+        F: Fn(i64, i64) -> i64 + Copy + 'static,
+    {
+        match Operand::new(b) {
+            Operand::Immediate(value) => with_dest(opcode, a, Immediate(value), dest, f),
+            Operand::Position(index) => with_dest(opcode, a, Position(index), dest, f),
+            Operand::Relative(offset) => with_dest(opcode, a, Relative(offset), dest, f),
+            Operand::Invalid(_) => decoded_step(opcode),
+        }
+    }
+
+    fn with_dest<A, B, F>(opcode: Opcode, a: A, b: B, dest: Parameter, f: F) -> Step
+    where
+        A: Source,
+        B: Source,
+        F: Fn(i64, i64) -> i64 + Copy + 'static,
+    {
+        match Operand::new(dest) {
+            Operand::Position(index) => Box::new(move |m| m.arithmetic(a, b, Position(index), f)),
+            Operand::Relative(offset) => Box::new(move |m| m.arithmetic(a, b, Relative(offset), f)),
+            _ => decoded_step(opcode),
+        }
+    }
+
+    match Operand::new(a) {
+        Operand::Immediate(value) => with_b(opcode, Immediate(value), b, dest, f),
+        Operand::Position(index) => with_b(opcode, Position(index), b, dest, f),
+        Operand::Relative(offset) => with_b(opcode, Relative(offset), b, dest, f),
+        Operand::Invalid(_) => decoded_step(opcode),
+    }
+}
+
+fn jump_step(opcode: Opcode, condition: Parameter, target: Parameter, when: bool) -> Step {
+    fn with_target<C: Source>(opcode: Opcode, condition: C, target: Parameter, when: bool) -> Step {
+        match Operand::new(target) {
+            Operand::Immediate(value) => {
+                Box::new(move |m| m.jump_if(condition, Immediate(value), when))
+            }
+            Operand::Position(index) => {
+                Box::new(move |m| m.jump_if(condition, Position(index), when))
+            }
+            Operand::Relative(offset) => {
+                Box::new(move |m| m.jump_if(condition, Relative(offset), when))
+            }
+            Operand::Invalid(_) => decoded_step(opcode),
+        }
+    }
+
+    match Operand::new(condition) {
+        Operand::Immediate(value) => with_target(opcode, Immediate(value), target, when),
+        Operand::Position(index) => with_target(opcode, Position(index), target, when),
+        Operand::Relative(offset) => with_target(opcode, Relative(offset), target, when),
+        Operand::Invalid(_) => decoded_step(opcode),
+    }
+}
+
+impl Block {
+    // runs instructions until one doesn't just fall through to the next, the block runs out, or
+    // one writes over compiled code
+    #[inline(always)]
+    fn run(&self, machine: &mut Machine) -> BlockExit {
+        let last = self.steps.len() - 1;
+
+        for (n, &(address, len, ref step)) in self.steps.iter().enumerate() {
+            match step(machine) {
+                Ok(Flow::Next) if n < last && !machine.modified => (),
+                flow => return (n as u64, address, len, flow),
+            }
+        }
+
+        unreachable!("blocks are never empty")
+    }
+}
+
+impl CompiledVM {
+    pub fn new(memory: Vec<i64>) -> Self {
+        CompiledVM {
+            machine: Machine::new(memory),
+            ip: 0,
+            blocks: Vec::new(),
+            starts: Vec::new(),
+            visits: Vec::new(),
+            steps: 0,
+            step_limit: None,
+            device_error: None,
+        }
+    }
+
+    pub fn input(&mut self, value: i64) {
+        self.machine.input.push_back(value);
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // the same budget IntcodeVM has, counted the same way
+    pub fn set_step_budget(&mut self, steps: Option<u64>) {
+        self.step_limit = steps.map(|steps| self.steps.saturating_add(steps));
+    }
+
+    pub fn step_budget(&self) -> Option<u64> {
+        self.step_limit
+            .map(|limit| limit.saturating_sub(self.steps))
+    }
+
+    // whether the budget covers this many more steps
+    #[inline]
+    fn within_budget(&self, steps: u64) -> bool {
+        self.step_limit
+            .is_none_or(|limit| self.steps.saturating_add(steps) <= limit)
+    }
+
+    // the error that stopped this VM while another was reading from it, if any
+    pub fn device_error(&self) -> Option<&IntcodeError> {
+        self.device_error.as_ref()
+    }
+
+    // every word that might be non-zero, in address order
+    pub fn cells(&self) -> Vec<(usize, i64)> {
+        let near = self.machine.near.iter().copied().enumerate();
+        near.chain(self.machine.far.cells()).collect()
+    }
+
+    fn check_budget(&self, ip: usize) -> Result<(), IntcodeError> {
+        if !self.within_budget(1) {
+            Err(IntcodeError::BudgetExhausted {
+                ip,
+                instruction: self.machine.load(ip),
+                limit: Limit::Steps,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    // a block starting at the address, or nothing if it starts with a patched word or past
+    // COMPILE_LIMIT
+    fn compile(&self, start: usize) -> Result<Option<Block>, IntcodeError> {
+        let machine = &self.machine;
+        let mut steps = Vec::new();
+        let mut address = start;
+
+        loop {
+            let opcode = match Opcode::decode(address, |index| machine.load(index)) {
+                Ok(opcode) => opcode,
+                // leave the fault to be reported once execution actually reaches it
+                Err(_) if !steps.is_empty() => break,
+                Err(fault) => return Err(fault.at(address, machine.load(address))),
+            };
+            let len = opcode.len();
+
+            if address + len > COMPILE_LIMIT
+                || (address..address + len).any(|index| self.machine.is_patched(index))
+            {
+                if steps.is_empty() {
+                    return Ok(None);
+                }
+
+                break;
+            }
+
+            // an input that has to wait should be at the start of a block, so running can resume
+            // from there
+            if let (Opcode::Input(_), false) = (opcode, steps.is_empty()) {
+                break;
+            }
+
+            steps.push((address, len, compile_step(opcode)));
+            address += len;
+
+            if ends_block(opcode) || steps.len() == MAX_BLOCK_LEN {
+                break;
+            }
+        }
+
+        Ok(Some(Block {
+            steps,
+            successors: [None; 2],
+        }))
+    }
+
+    // where in blocks the block at the address is, compiling it if it's hot and hasn't been
+    // compiled yet. nothing if it's not hot yet or can't be compiled
+    fn block_at(&mut self, ip: usize) -> Result<Option<usize>, IntcodeError> {
+        if let Some(&Some(index)) = self.starts.get(ip) {
+            return Ok(Some(index));
+        }
+
+        if !self.visit(ip) {
+            return Ok(None);
+        }
+
+        // compiling reports faults in the first instruction, which mustn't happen before the
+        // budget runs out
+        self.check_budget(ip)?;
+
+        let block = match self.compile(ip)? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+
+        if self.starts.len() <= ip {
+            self.starts.resize(ip + 1, None);
+        }
+
+        self.mark_code(&block);
+        self.starts[ip] = Some(self.blocks.len());
+        self.blocks.push(block);
+        Ok(self.starts[ip])
+    }
+
+    // counts execution reaching an address, returns whether it's been reached often enough to
+    // compile
+    fn visit(&mut self, ip: usize) -> bool {
+        if ip >= COMPILE_LIMIT {
+            return false;
+        }
+
+        // sized for the whole program the first time, so it doesn't grow an address at a time
+        if self.visits.len() <= ip {
+            let len = self.machine.near.len().max(ip + 1);
+            self.visits.resize(len, 0);
+        }
+
+        let visits = &mut self.visits[ip];
+        *visits = (*visits + 1).min(COMPILE_THRESHOLD);
+        *visits == COMPILE_THRESHOLD
+    }
+
+    // decodes and runs the instruction at ip
+    fn interpret<R, W>(
+        &mut self,
+        read: &mut R,
+        write: &mut W,
+    ) -> Result<Option<RunState>, IntcodeError>
+    where
+        R: FnMut() -> Option<i64>,
+        W: FnMut(i64) -> bool,
+    {
+        let ip = self.ip;
+        self.check_budget(ip)?;
+
+        let machine = &self.machine;
+        let fault = |fault: Fault, machine: &Machine| fault.at(ip, machine.load(ip));
+        let opcode = Opcode::decode(ip, |index| machine.load(index))
+            .map_err(|error| fault(error, machine))?;
+
+        let flow = loop {
+            match self.machine.execute(opcode) {
+                Ok(Flow::NeedsInput) => match read() {
+                    Some(value) => self.input(value),
+                    None => return Ok(Some(RunState::NeedsInput)),
+                },
+                flow => break flow.map_err(|error| fault(error, &self.machine))?,
+            }
+        };
+
+        self.steps += 1;
+
+        let state = match flow {
+            Flow::Jump(target) => {
+                self.ip = target;
+                None
+            }
+            Flow::Output(value) => {
+                self.ip += opcode.len();
+
+                if write(value) {
+                    None
+                } else {
+                    Some(RunState::Output(value))
+                }
+            }
+            Flow::Halt => Some(RunState::Halted),
+            _ => {
+                self.ip += opcode.len();
+                None
+            }
+        };
+
+        if self.machine.modified {
+            self.invalidate();
+        }
+
+        Ok(state)
+    }
+
+    fn mark_code(&mut self, block: &Block) {
+        let end = block
+            .steps
+            .last()
+            .map_or(0, |&(address, len, _)| address + len);
+
+        if self.machine.code.len() < end {
+            self.machine.code.resize(end, false);
+            self.machine.patched.resize(end, false);
+        }
+
+        for &(address, len, _) in &block.steps {
+            for word in &mut self.machine.code[address..address + len] {
+                *word = true;
+            }
+        }
+    }
+
+    // a write hit compiled code. everything is thrown away and recompiled lazily, leaving the
+    // patched words out of it from now on
+    fn invalidate(&mut self) {
+        self.blocks.clear();
+        self.machine.modified = false;
+
+        for start in &mut self.starts {
+            *start = None;
+        }
+
+        for word in &mut self.machine.code {
+            *word = false;
+        }
+    }
+
+    // runs until the program stops. read is asked for input once the queue runs dry, and outputs
+    // go to write, which returns whether the run carries on
+    fn run_until<R, W>(&mut self, read: &mut R, write: &mut W) -> Result<RunState, IntcodeError>
+    where
+        R: FnMut() -> Option<i64>,
+        W: FnMut(i64) -> bool,
+    {
+        // where in blocks the block at ip is, when the last block knew
+        let mut next = None;
+
+        loop {
+            let index = match next {
+                Some(index) => index,
+                None => match self.block_at(self.ip)? {
+                    Some(index) => index,
+                    None => {
+                        if let Some(state) = self.interpret(read, write)? {
+                            return Ok(state);
+                        }
+
+                        continue;
+                    }
+                },
+            };
+            let block = &self.blocks[index];
+
+            // a block the budget doesn't cover is run an instruction at a time, so the budget
+            // runs out at the right one
+            if !self.within_budget(block.steps.len() as u64) {
+                next = None;
+
+                if let Some(state) = self.interpret(read, write)? {
+                    return Ok(state);
+                }
+
+                continue;
+            }
+
+            let (finished, address, len, flow) = block.run(&mut self.machine);
+            self.steps += finished;
+            self.ip = address;
+
+            let flow = flow.map_err(|fault| fault.at(address, self.machine.load(address)))?;
+            let exit = match flow {
+                // inputs only ever start blocks, so the block is run again once there's input
+                Flow::NeedsInput => match read() {
+                    Some(value) => {
+                        self.input(value);
+                        continue;
+                    }
+                    None => return Ok(RunState::NeedsInput),
+                },
+                Flow::Halt => {
+                    self.steps += 1;
+                    return Ok(RunState::Halted);
+                }
+                Flow::Output(value) if !write(value) => {
+                    self.steps += 1;
+                    self.ip += len;
+                    return Ok(RunState::Output(value));
+                }
+                Flow::Jump(target) => {
+                    self.ip = target;
+                    1
+                }
+                _ => {
+                    self.ip += len;
+                    0
+                }
+            };
+
+            self.steps += 1;
+
+            if self.machine.modified {
+                self.invalidate();
+                next = None;
+                continue;
+            }
+
+            // a successor that isn't compiled yet is left for block_at to count the visit to
+            next = match block.successors[exit] {
+                Some((address, index)) if address == self.ip => Some(index),
+                _ => {
+                    let successor = self.starts.get(self.ip).copied().flatten();
+
+                    if let Some(successor) = successor {
+                        self.blocks[index].successors[exit] = Some((self.ip, successor));
+                    }
+
+                    successor
+                }
+            };
+        }
+    }
+
+    pub fn run(&mut self) -> Result<RunState, IntcodeError> {
+        self.run_until(&mut || None, &mut |_| false)
+    }
+
+    pub fn get_next_output(&mut self) -> Result<Option<i64>, IntcodeError> {
+        loop {
+            match self.run()? {
+                RunState::Output(value) => break Ok(Some(value)),
+                RunState::Halted => break Ok(None),
+                RunState::NeedsInput => {
+                    break Err(IntcodeError::InputUnderflow {
+                        ip: self.ip,
+                        instruction: self.machine.load(self.ip),
+                    })
+                }
+                // nothing here sets watchpoints, but there's nothing to do about one either
+                RunState::Watchpoint(_) => (),
+            }
+        }
+    }
+
+    pub fn run_with<I, O>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<RunState, IntcodeError>
+    where
+        I: InputDevice + ?Sized,
+        O: OutputDevice + ?Sized,
+    {
+        self.run_until(&mut || input.read(), &mut |value| {
+            output.write(value);
+            true
+        })
+    }
+}
+
+// the same as reading from an IntcodeVM, a failure is kept for device_error
+impl InputDevice for CompiledVM {
+    fn read(&mut self) -> Option<i64> {
+        if self.device_error.is_some() {
+            return None;
+        }
+
+        match self.run() {
+            Ok(RunState::Output(value)) => Some(value),
+            Ok(_) => None,
+            Err(e) => {
+                self.device_error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+impl OutputDevice for CompiledVM {
+    fn write(&mut self, value: i64) {
+        self.input(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{asm::assemble, IntcodeError, IntcodeVM, Limit, RunState},
+        CompiledVM,
+    };
+
+    // a countdown from 1000, then one in twos from 2000
+    fn countdowns() -> Vec<i64> {
+        (0..1000)
+            .rev()
+            .chain((0..1000).rev().map(|n| n * 2))
+            .collect()
+    }
+
+    #[test]
+    fn compiled_engine_matches_interpreter() {
+        // counts down, then patches the loop to count down in twos once it's compiled and runs it
+        // again
+        let program = assemble(
+            "
+            INPUT [counter]
+            ADD [counter], #0, [start]
+        loop:
+            ADD [counter], #-1, [counter]
+            OUTPUT [counter]
+            JUMP_IF_TRUE [counter], #loop
+            JUMP_IF_TRUE [done], #end
+            ADD #1, #0, [done]
+            ADD #0, #-2, [loop+2]
+            MUL [start], #2, [counter]
+            JUMP_IF_TRUE #1, #loop
+        end:
+            HALT
+        counter:
+            .data 0
+        start:
+            .data 0
+        done:
+            .data 0
+        ",
+        )
+        .unwrap();
+
+        let mut interpreted = IntcodeVM::new(program.clone());
+        let mut compiled = CompiledVM::new(program);
+        assert_eq!(interpreted.run(), Ok(RunState::NeedsInput));
+        assert_eq!(compiled.run(), Ok(RunState::NeedsInput));
+
+        interpreted.input(1000);
+        compiled.input(1000);
+        let mut expected = Vec::new();
+        let mut found = Vec::new();
+        interpreted.run_with(&mut || None, &mut expected).unwrap();
+        compiled.run_with(&mut || None, &mut found).unwrap();
+
+        assert_eq!(expected, countdowns());
+        assert_eq!(found, expected);
+        assert_eq!(compiled.steps(), interpreted.steps());
+    }
+
+    #[test]
+    fn compiled_engine_runs_and_patches_code_at_huge_addresses() {
+        // a countdown loop is stored far out, run, patched to count down in twos and run again
+        let high = 1_i64 << 40;
+        let counter = high + 12;
+        let code = [
+            format!("#{}", 1001),
+            format!("#{}", counter),
+            String::from("#-1"),
+            format!("#{}", counter),
+            String::from("#4"),
+            format!("#{}", counter),
+            String::from("#1005"),
+            format!("#{}", counter),
+            format!("#{}", high),
+            String::from("#1105"),
+            String::from("#1"),
+            String::from("#back"),
+            String::from("#1000"),
+        ];
+        let stores: Vec<_> = code
+            .iter()
+            .enumerate()
+            .map(|(i, word)| format!("ADD {}, #0, [{}]", word, high + i as i64))
+            .collect();
+        let program = assemble(&format!(
+            "
+            {}
+            JUMP_IF_TRUE #1, #{high}
+        back:
+            JUMP_IF_TRUE [done], #end
+            ADD #1, #0, [done]
+            ADD #0, #-2, [{patch}]
+            ADD #2000, #0, [{counter}]
+            JUMP_IF_TRUE #1, #{high}
+        end:
+            HALT
+        done:
+            .data 0
+        ",
+            stores.join("\n"),
+            high = high,
+            patch = high + 2,
+            counter = counter,
+        ))
+        .unwrap();
+
+        let mut interpreted = IntcodeVM::new(program.clone());
+        let mut compiled = CompiledVM::new(program);
+        let mut expected = Vec::new();
+        let mut found = Vec::new();
+        interpreted.run_with(&mut || None, &mut expected).unwrap();
+        compiled.run_with(&mut || None, &mut found).unwrap();
+
+        assert_eq!(expected, countdowns());
+        assert_eq!(found, expected);
+        assert_eq!(compiled.steps(), interpreted.steps());
+    }
+
+    #[test]
+    fn only_code_that_runs_often_is_compiled() {
+        let program = assemble(
+            "
+            INPUT [counter]
+        loop:
+            ADD [counter], #-1, [counter]
+            JUMP_IF_TRUE [counter], #loop
+            HALT
+        counter:
+            .data 0
+        ",
+        )
+        .unwrap();
+
+        let mut vm = CompiledVM::new(program.clone());
+        vm.input(3);
+        assert_eq!(vm.run(), Ok(RunState::Halted));
+        assert!(vm.blocks.is_empty());
+
+        let mut vm = CompiledVM::new(program);
+        vm.input(10);
+        assert_eq!(vm.run(), Ok(RunState::Halted));
+        assert_eq!(vm.blocks.len(), 1);
+        assert_eq!(vm.steps(), 22);
+    }
+
+    #[test]
+    fn budgets_run_out_in_the_middle_of_compiled_blocks() {
+        let program = assemble(
+            "
+        loop:
+            ADD [x], #1, [x]
+            ADD [y], #2, [y]
+            JUMP_IF_TRUE #1, #loop
+        x:
+            .data 0
+        y:
+            .data 0
+        ",
+        )
+        .unwrap();
+
+        let mut interpreted = IntcodeVM::new(program.clone());
+        let mut compiled = CompiledVM::new(program);
+        interpreted.set_step_budget(Some(5000));
+        compiled.set_step_budget(Some(5000));
+
+        let error = IntcodeError::BudgetExhausted {
+            ip: 8,
+            instruction: 1105,
+            limit: Limit::Steps,
+        };
+        assert_eq!(interpreted.run(), Err(error));
+        assert_eq!(compiled.run(), Err(error));
+        assert_eq!(compiled.steps(), 5000);
+        assert_eq!(compiled.step_budget(), Some(0));
+    }
+
+    #[test]
+    fn compiled_code_waits_for_input() {
+        let mut vm = CompiledVM::new(
+            assemble(
+                "
+            loop:
+                INPUT [value]
+                ADD [sum], [value], [sum]
+                OUTPUT [sum]
+                JUMP_IF_TRUE #1, #loop
+            value:
+                .data 0
+            sum:
+                .data 0
+            ",
+            )
+            .unwrap(),
+        );
+
+        for value in 1..=1000 {
+            assert_eq!(vm.run(), Ok(RunState::NeedsInput));
+            vm.input(value);
+            assert_eq!(vm.run(), Ok(RunState::Output(value * (value + 1) / 2)));
+        }
+
+        assert_eq!(vm.steps(), 3999);
+    }
+
+    #[test]
+    fn failing_compiled_vm_keeps_its_error() {
+        let mut upstream = CompiledVM::new(vec![104, 7, 42]);
+        let mut sink = Vec::new();
+
+        assert_eq!(
+            IntcodeVM::new(vec![3, 9, 4, 9, 3, 9, 4, 9, 99, 0]).run_with(&mut upstream, &mut sink),
+            Ok(RunState::NeedsInput)
+        );
+        assert_eq!(sink, vec![7]);
+        assert_eq!(
+            upstream.device_error(),
+            Some(&IntcodeError::UnknownOpcode {
+                ip: 2,
+                instruction: 42,
+            })
+        );
+    }
+}
@@ -0,0 +1,443 @@
+use super::{
+    watch::{Access, WatchKind},
+    IntcodeError, IntcodeVM, Opcode, RunState,
+};
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{self, BufRead, Write},
+};
+
+const HELP: &str = "\
+commands:
+  s, step [n]           execute n instructions (default 1)
+  c, continue           run until a breakpoint, output, input request or halt
+  b, break [addr]       set a breakpoint at addr, or list breakpoints
+  d, delete <addr>      remove the breakpoint at addr
+  r, regs               show the registers and pending input
+  x, mem <addr> [len]   dump len words of memory starting at addr (default 8)
+  p, poke <addr> <val>  write val to memory at addr
+  i, input <val>...     queue input values
+  watch <addr> [len]    pause after writes to len words starting at addr (default 1)
+  rwatch <addr> [len]   pause after reads from the words
+  awatch <addr> [len]   pause after reads from or writes to the words
+  unwatch <id>          remove a watchpoint
+  l, list [addr] [n]    disassemble n instructions starting at addr (default ip, 8)
+  h, help               show this help
+  q, quit               leave the debugger";
+// the most words mem and list will show, and instructions step will run, at once
+const MAX_WORDS: usize = 4096;
+
+#[derive(Debug)]
+pub struct Debugger {
+    vm: IntcodeVM,
+    breakpoints: BTreeSet<usize>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    Breakpoint(usize),
+    State(RunState),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registers {
+    pub ip: usize,
+    pub rel_base_offset: i64,
+    pub input: Vec<i64>,
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ip: {}, rel base: {}, input: {:?}",
+            self.ip, self.rel_base_offset, self.input
+        )
+    }
+}
+
+impl Debugger {
+    pub fn new(vm: IntcodeVM) -> Self {
+        Debugger {
+            vm,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn vm(&self) -> &IntcodeVM {
+        &self.vm
+    }
+
+    pub fn into_inner(self) -> IntcodeVM {
+        self.vm
+    }
+
+    pub fn input(&mut self, value: i64) {
+        self.vm.input(value);
+    }
+
+    pub fn step(&mut self) -> Result<Option<RunState>, IntcodeError> {
+        self.vm.step()
+    }
+
+    pub fn continue_execution(&mut self) -> Result<Event, IntcodeError> {
+        // always execute at least one instruction so continuing from a breakpoint doesn't just
+        // stop on it again
+        loop {
+            if let Some(state) = self.vm.step()? {
+                break Ok(Event::State(state));
+            }
+
+            if self.breakpoints.contains(&self.vm.ip) {
+                break Ok(Event::Breakpoint(self.vm.ip));
+            }
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            ip: self.vm.ip,
+            rel_base_offset: self.vm.rel_base_offset,
+            input: self.vm.input.iter().copied().collect(),
+        }
+    }
+
+    pub fn dump(&self, address: usize, len: usize) -> Vec<i64> {
+        self.vm.dump(address..address.saturating_add(len)).collect()
+    }
+
+    pub fn poke(&mut self, address: usize, value: i64) {
+        self.vm.write(address, value);
+    }
+
+    // returns the decoded instruction at the given address and its length
+    pub fn instruction_at(&self, address: usize) -> (String, usize) {
+        match Opcode::from_memory(address, &self.vm.memory, &self.vm.extensions) {
+            Ok(opcode) => (
+                opcode.listing(self.vm.extensions.mnemonic(opcode)),
+                opcode.len(),
+            ),
+            Err(_) => (format!("DATA {}", self.vm.memory.fetch(address)), 1),
+        }
+    }
+
+    pub fn repl<R, W>(&mut self, input: R, mut output: W) -> io::Result<()>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        write!(output, "> ")?;
+        output.flush()?;
+
+        for line in input.lines() {
+            let line = line?;
+            let mut words = line.split_whitespace();
+
+            if let Some(command) = words.next() {
+                let args = words.map(str::parse).collect::<Result<Vec<i64>, _>>();
+
+                match args {
+                    Ok(args) => {
+                        if !self.command(command, &args, &mut output)? {
+                            break;
+                        }
+                    }
+                    Err(e) => writeln!(output, "invalid argument: {}", e)?,
+                }
+            }
+
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+
+        Ok(())
+    }
+
+    // returns false when the REPL should exit
+    fn command<W: Write>(
+        &mut self,
+        command: &str,
+        args: &[i64],
+        output: &mut W,
+    ) -> io::Result<bool> {
+        // everything but poked values and input is an address, a length or an id
+        let unsigned = match command {
+            "p" | "poke" => &args[..args.len().min(1)],
+            "i" | "input" => &[],
+            _ => args,
+        };
+        if let Some(arg) = unsigned.iter().find(|&&arg| arg < 0) {
+            writeln!(
+                output,
+                "invalid argument {}, expected a non-negative number",
+                arg
+            )?;
+            return Ok(true);
+        }
+
+        let address = |index: usize| args.get(index).map(|&arg| arg as usize);
+        match command {
+            "s" | "step" if address(0).unwrap_or(0) > MAX_WORDS => writeln!(
+                output,
+                "at most {} instructions can be stepped at once",
+                MAX_WORDS
+            )?,
+            "s" | "step" => {
+                for _ in 0..args.first().copied().unwrap_or(1).max(1) {
+                    let (instruction, _) = self.instruction_at(self.vm.ip);
+                    writeln!(output, "{:04}: {}", self.vm.ip, instruction)?;
+
+                    match self.step() {
+                        Ok(None) => (),
+                        Ok(Some(state)) => {
+                            writeln!(output, "{}", describe(state))?;
+                            break;
+                        }
+                        Err(e) => {
+                            writeln!(output, "error: {}", e)?;
+                            break;
+                        }
+                    }
+                }
+            }
+            "c" | "continue" => match self.continue_execution() {
+                Ok(Event::Breakpoint(ip)) => writeln!(output, "breakpoint at {}", ip)?,
+                Ok(Event::State(state)) => writeln!(output, "{}", describe(state))?,
+                Err(e) => writeln!(output, "error: {}", e)?,
+            },
+            "b" | "break" => match address(0) {
+                Some(address) => {
+                    self.add_breakpoint(address);
+                }
+                None => writeln!(output, "breakpoints: {:?}", self.breakpoints)?,
+            },
+            "d" | "delete" => match address(0) {
+                Some(address) => {
+                    if !self.remove_breakpoint(address) {
+                        writeln!(output, "no breakpoint at {}", address)?;
+                    }
+                }
+                None => writeln!(output, "usage: delete <addr>")?,
+            },
+            "r" | "regs" => writeln!(output, "{}", self.registers())?,
+            "x" | "mem" => match address(0) {
+                Some(_) if address(1).unwrap_or(0) > MAX_WORDS => {
+                    writeln!(output, "at most {} words can be shown at once", MAX_WORDS)?
+                }
+                Some(start) => {
+                    let len = address(1).unwrap_or(8);
+
+                    for (row, chunk) in self.dump(start, len).chunks(8).enumerate() {
+                        let words = chunk
+                            .iter()
+                            .map(|word| word.to_string())
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        writeln!(output, "{:04}: {}", start + row * 8, words)?;
+                    }
+                }
+                None => writeln!(output, "usage: mem <addr> [len]")?,
+            },
+            "p" | "poke" => match (address(0), args.get(1)) {
+                (Some(address), Some(&value)) => self.poke(address, value),
+                _ => writeln!(output, "usage: poke <addr> <val>")?,
+            },
+            "i" | "input" => args.iter().for_each(|&value| self.input(value)),
+            "watch" | "rwatch" | "awatch" => match address(0) {
+                Some(start) => {
+                    let kind = match command {
+                        "watch" => WatchKind::Write,
+                        "rwatch" => WatchKind::Read,
+                        _ => WatchKind::Access,
+                    };
+                    let end = start.saturating_add(address(1).unwrap_or(1));
+                    let id = self.vm.add_watchpoint(start..end, kind);
+                    writeln!(output, "watchpoint {} on {}..{}", id, start, end)?;
+                }
+                None => writeln!(output, "usage: {} <addr> [len]", command)?,
+            },
+            "unwatch" => match address(0) {
+                Some(id) => {
+                    if !self.vm.remove_watchpoint(id) {
+                        writeln!(output, "no watchpoint {}", id)?;
+                    }
+                }
+                None => writeln!(output, "usage: unwatch <id>")?,
+            },
+            "l" | "list" if address(1).unwrap_or(0) > MAX_WORDS => writeln!(
+                output,
+                "at most {} instructions can be shown at once",
+                MAX_WORDS
+            )?,
+            "l" | "list" => {
+                let count = address(1).unwrap_or(8);
+                let mut address = address(0).unwrap_or(self.vm.ip);
+
+                for _ in 0..count {
+                    let (instruction, len) = self.instruction_at(address);
+                    let marker = if address == self.vm.ip { "=>" } else { "  " };
+                    writeln!(output, "{} {:04}: {}", marker, address, instruction)?;
+                    address = match address.checked_add(len) {
+                        Some(next) => next,
+                        None => break,
+                    };
+                }
+            }
+            "h" | "help" => writeln!(output, "{}", HELP)?,
+            "q" | "quit" => return Ok(false),
+            _ => writeln!(output, "unknown command {}, try help", command)?,
+        }
+
+        Ok(true)
+    }
+}
+
+fn describe(state: RunState) -> String {
+    match state {
+        RunState::NeedsInput => String::from("waiting for input"),
+        RunState::Output(value) => format!("output: {}", value),
+        RunState::Halted => String::from("halted"),
+        RunState::Watchpoint(hit) => format!(
+            "watchpoint {}: {} {} at {} by the instruction at {}",
+            hit.watchpoint,
+            match hit.access {
+                Access::Read => "read",
+                Access::Write => "wrote",
+            },
+            hit.value,
+            hit.address,
+            hit.ip
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::asm::assemble, Debugger, IntcodeVM};
+
+    fn debugger() -> Debugger {
+        let program = assemble(
+            "
+        loop:
+            ADD [counter], #-1, [counter]
+            OUTPUT [counter]
+            JUMP_IF_TRUE [counter], #loop
+            HALT
+        counter:
+            .data 2
+        ",
+        )
+        .unwrap();
+
+        Debugger::new(IntcodeVM::new(program))
+    }
+
+    fn command(debugger: &mut Debugger, line: &str) -> String {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap();
+        let args: Vec<i64> = words.map(|word| word.parse().unwrap()).collect();
+        let mut output = Vec::new();
+
+        assert!(debugger.command(command, &args, &mut output).unwrap());
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let mut debugger = debugger();
+
+        assert_eq!(command(&mut debugger, "b 4"), "");
+        assert_eq!(command(&mut debugger, "b"), "breakpoints: {4}\n");
+        assert_eq!(command(&mut debugger, "c"), "breakpoint at 4\n");
+        assert_eq!(
+            command(&mut debugger, "s"),
+            "0004: OUTPUT [10]\noutput: 1\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "step 2"),
+            "0006: JUMP_IF_TRUE [10], #0\n0000: ADD [10], #-1, [10]\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "r"),
+            "ip: 4, rel base: 0, input: []\n"
+        );
+
+        assert_eq!(command(&mut debugger, "d 4"), "");
+        assert_eq!(command(&mut debugger, "d 4"), "no breakpoint at 4\n");
+        assert_eq!(command(&mut debugger, "c"), "output: 0\n");
+        assert_eq!(command(&mut debugger, "c"), "halted\n");
+    }
+
+    #[test]
+    fn memory_can_be_dumped_and_poked() {
+        let mut debugger = debugger();
+
+        assert_eq!(
+            command(&mut debugger, "x 0 10"),
+            "0000: 1001 10 -1 10 4 10 1005 10\n0008: 0 99\n"
+        );
+        assert_eq!(command(&mut debugger, "p 10 -5"), "");
+        assert_eq!(command(&mut debugger, "mem 10 1"), "0010: -5\n");
+
+        // far past the end of the program
+        assert_eq!(command(&mut debugger, "poke 1000000000000 7"), "");
+        assert_eq!(
+            command(&mut debugger, "x 999999999999 3"),
+            "999999999999: 0 7 0\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "x 0 1000000000"),
+            "at most 4096 words can be shown at once\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "l 0 1000000000"),
+            "at most 4096 instructions can be shown at once\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "step 9223372036854775807"),
+            "at most 4096 instructions can be stepped at once\n"
+        );
+        assert_eq!(debugger.registers().ip, 0);
+    }
+
+    #[test]
+    fn invalid_commands_change_nothing() {
+        let mut debugger = debugger();
+
+        assert_eq!(
+            command(&mut debugger, "p -5 42"),
+            "invalid argument -5, expected a non-negative number\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "b -1"),
+            "invalid argument -1, expected a non-negative number\n"
+        );
+        assert_eq!(
+            command(&mut debugger, "x 0 -1"),
+            "invalid argument -1, expected a non-negative number\n"
+        );
+        assert_eq!(debugger.vm().read(0), 1001);
+        assert_eq!(debugger.breakpoints().count(), 0);
+
+        assert_eq!(command(&mut debugger, "x"), "usage: mem <addr> [len]\n");
+        assert_eq!(command(&mut debugger, "p 3"), "usage: poke <addr> <val>\n");
+        assert_eq!(
+            command(&mut debugger, "jump 4"),
+            "unknown command jump, try help\n"
+        );
+        assert_eq!(debugger.registers().ip, 0);
+    }
+}
@@ -0,0 +1,776 @@
+use super::{
+    cfg::{jump, successors},
+    disasm::decode_at,
+    Opcode, Parameter,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+// the compiler behind the puzzle inputs calls functions by storing the return address in rel[0]
+// and the arguments in rel[1], rel[2]... before jumping. the function then moves the relative
+// base past the caller's slots, so its return address and arguments end up right below its own
+// relative base, and it returns by moving the base back and jumping through rel[0]
+
+// stores to argument slots past this are taken to be something other than arguments, since the
+// slots come straight from the program
+const MAX_ARGS: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub entry: usize,
+    // how far the prologue moves the relative base
+    pub frame: Option<i64>,
+    // the most arguments any call site passes
+    pub args: usize,
+    // whether a call site stores to slots past MAX_ARGS, so the arguments can't all be named
+    unknown_arity: bool,
+    instructions: BTreeMap<usize, Opcode>,
+    invalid: BTreeSet<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Decompiled {
+    functions: BTreeMap<usize, Function>,
+    calls: BTreeMap<usize, CallSite>,
+}
+
+// keyed by the address of the jump
+#[derive(Debug, Clone)]
+struct CallSite {
+    start: usize,
+    target: usize,
+    // the argument slots and the instructions storing them, in order
+    args: Vec<(i64, Opcode)>,
+    // the arguments stopped at a store to a slot past MAX_ARGS
+    unknown_arity: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Flow {
+    Always,
+    If(String),
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Statement(String),
+    Jump(Flow, usize),
+    IndirectJump(Flow, String),
+    Return,
+}
+
+// one or more instructions rendered as a single statement
+#[derive(Debug, Clone)]
+struct Group {
+    start: usize,
+    end: usize,
+    item: Option<Item>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Loop {
+    header: usize,
+    end: usize,
+    closing: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Names {
+    frame: Option<i64>,
+    args: usize,
+}
+
+fn constant_store(opcode: Opcode) -> Option<(i64, Parameter)> {
+    match opcode {
+        Opcode::Add(Parameter::Immediate(a), Parameter::Immediate(b), dest) => {
+            Some((a.wrapping_add(b), dest))
+        }
+        Opcode::Mul(Parameter::Immediate(a), Parameter::Immediate(b), dest) => {
+            Some((a.wrapping_mul(b), dest))
+        }
+        _ => None,
+    }
+}
+
+fn previous(program: &[i64], address: usize, len: usize) -> Option<Opcode> {
+    decode_at(program, address.checked_sub(len)?).filter(|opcode| opcode.len() == len)
+}
+
+// an unconditional jump to a function right after storing the address following the jump
+fn call_target(program: &[i64], address: usize, opcode: Opcode) -> Option<usize> {
+    let target = match jump(opcode) {
+        Some((Some(true), Some(target))) => target,
+        _ => return None,
+    };
+
+    match previous(program, address, 4).and_then(constant_store) {
+        Some((value, Parameter::Relative(0))) if value == (address + opcode.len()) as i64 => {
+            Some(target)
+        }
+        _ => None,
+    }
+}
+
+// moving the relative base back down and jumping through rel[0]
+fn is_return(opcode: Opcode, previous: Option<Opcode>) -> bool {
+    let through_rel = match opcode {
+        Opcode::JumpIfTrue(_, target) | Opcode::JumpIfFalse(_, target) => {
+            target == Parameter::Relative(0)
+        }
+        _ => false,
+    };
+
+    through_rel
+        && matches!(jump(opcode), Some((Some(true), _)))
+        && matches!(
+            previous,
+            Some(Opcode::RelativeBaseOffset(Parameter::Immediate(n))) if n < 0
+        )
+}
+
+impl Names {
+    fn operand(self, parameter: Parameter) -> String {
+        match parameter {
+            Parameter::Immediate(value) => value.to_string(),
+            Parameter::Position(address) => format!("mem[{}]", address),
+            Parameter::Relative(offset) => match self.frame {
+                Some(frame) if (-frame..0).contains(&offset) => {
+                    let slot = (offset + frame) as usize;
+
+                    if slot == 0 {
+                        String::from("return_address")
+                    } else if slot <= self.args {
+                        format!("arg{}", slot - 1)
+                    } else {
+                        format!("local{}", slot - 1 - self.args)
+                    }
+                }
+                Some(_) if offset >= 0 => format!("out{}", offset),
+                _ => format!("rb[{}]", offset),
+            },
+        }
+    }
+
+    fn add(self, a: Parameter, b: Parameter) -> String {
+        match (a, b) {
+            (Parameter::Immediate(a), Parameter::Immediate(b)) => a.wrapping_add(b).to_string(),
+            (p, Parameter::Immediate(0)) | (Parameter::Immediate(0), p) => self.operand(p),
+            (p, Parameter::Immediate(n)) | (Parameter::Immediate(n), p) if n < 0 => {
+                format!("{} - {}", self.operand(p), n.unsigned_abs())
+            }
+            _ => format!("{} + {}", self.operand(a), self.operand(b)),
+        }
+    }
+
+    fn mul(self, a: Parameter, b: Parameter) -> String {
+        match (a, b) {
+            (Parameter::Immediate(a), Parameter::Immediate(b)) => a.wrapping_mul(b).to_string(),
+            (p, Parameter::Immediate(1)) | (Parameter::Immediate(1), p) => self.operand(p),
+            (p, Parameter::Immediate(-1)) | (Parameter::Immediate(-1), p) => {
+                format!("-{}", self.operand(p))
+            }
+            _ => format!("{} * {}", self.operand(a), self.operand(b)),
+        }
+    }
+
+    // the value an arithmetic or comparison instruction computes. negated comparisons are
+    // written the other way around
+    fn expression(self, opcode: Opcode, negate: bool) -> Option<String> {
+        Some(match opcode {
+            Opcode::Add(a, b, _) => self.add(a, b),
+            Opcode::Mul(a, b, _) => self.mul(a, b),
+            Opcode::LessThan(a, b, _) => {
+                let comparison = if negate { ">=" } else { "<" };
+                format!("{} {} {}", self.operand(a), comparison, self.operand(b))
+            }
+            Opcode::Equals(a, b, _) => {
+                let comparison = if negate { "!=" } else { "==" };
+                format!("{} {} {}", self.operand(a), comparison, self.operand(b))
+            }
+            _ => return None,
+        })
+    }
+
+    fn item(self, opcode: Opcode) -> Option<Item> {
+        let statement = match opcode {
+            Opcode::Add(.., dest)
+            | Opcode::Mul(.., dest)
+            | Opcode::LessThan(.., dest)
+            | Opcode::Equals(.., dest) => format!(
+                "{} = {};",
+                self.operand(dest),
+                self.expression(opcode, false)?
+            ),
+            Opcode::Input(dest) => format!("{} = input();", self.operand(dest)),
+            Opcode::Output(value) => format!("output({});", self.operand(value)),
+            Opcode::RelativeBaseOffset(Parameter::Immediate(n)) if n < 0 => {
+                format!("rb -= {};", n.unsigned_abs())
+            }
+            Opcode::RelativeBaseOffset(offset) => format!("rb += {};", self.operand(offset)),
+            Opcode::Halt => String::from("halt;"),
+            Opcode::Custom(custom) => {
+                let operands: Vec<_> = custom
+                    .parameters()
+                    .iter()
+                    .map(|&parameter| self.operand(parameter))
+                    .collect();
+                format!("op{}({});", custom.number, operands.join(", "))
+            }
+            Opcode::JumpIfTrue(condition, target) | Opcode::JumpIfFalse(condition, target) => {
+                let condition = if let Opcode::JumpIfTrue(..) = opcode {
+                    self.operand(condition)
+                } else {
+                    format!("!{}", self.operand(condition))
+                };
+
+                return self.jump(opcode, condition, target);
+            }
+        };
+
+        Some(Item::Statement(statement))
+    }
+
+    fn jump(self, opcode: Opcode, condition: String, target: Parameter) -> Option<Item> {
+        let flow = match jump(opcode)? {
+            (Some(true), _) => Flow::Always,
+            (Some(false), _) => return None,
+            (None, _) => Flow::If(condition),
+        };
+
+        Some(match target {
+            Parameter::Immediate(target) if target >= 0 => Item::Jump(flow, target as usize),
+            _ => Item::IndirectJump(flow, self.operand(target)),
+        })
+    }
+}
+
+fn name(entry: usize) -> String {
+    if entry == 0 {
+        String::from("main")
+    } else {
+        format!("func_{:04}", entry)
+    }
+}
+
+impl Function {
+    // everything reachable from the entry without following calls
+    fn explore(program: &[i64], entry: usize, callees: &mut Vec<usize>) -> Self {
+        let mut instructions = BTreeMap::new();
+        let mut invalid = BTreeSet::new();
+        let mut pending = vec![entry];
+
+        while let Some(address) = pending.pop() {
+            if instructions.contains_key(&address) || invalid.contains(&address) {
+                continue;
+            }
+
+            let opcode = match decode_at(program, address) {
+                Some(opcode) => opcode,
+                None => {
+                    invalid.insert(address);
+                    continue;
+                }
+            };
+            instructions.insert(address, opcode);
+
+            if let Some(target) = call_target(program, address, opcode) {
+                callees.push(target);
+                pending.push(address + opcode.len());
+            } else if !is_return(opcode, previous(program, address, 2)) {
+                pending.extend(successors(address, opcode));
+            }
+        }
+
+        // the program starts with the relative base at 0, so whatever it does first only sets up
+        // the stack
+        let frame = match instructions.get(&entry) {
+            Some(Opcode::RelativeBaseOffset(Parameter::Immediate(n))) if *n > 0 && entry != 0 => {
+                Some(*n)
+            }
+            _ => None,
+        };
+
+        Function {
+            entry,
+            frame,
+            args: 0,
+            unknown_arity: false,
+            instructions,
+            invalid,
+        }
+    }
+
+    fn before(&self, address: usize) -> Option<(usize, Opcode)> {
+        self.instructions
+            .range(..address)
+            .next_back()
+            .map(|(&start, &opcode)| (start, opcode))
+            .filter(|&(start, opcode)| start + opcode.len() == address)
+    }
+
+    fn returns_at(&self, address: usize, opcode: Opcode) -> bool {
+        is_return(opcode, self.before(address).map(|(_, opcode)| opcode))
+    }
+
+    fn jump_targets(&self) -> BTreeSet<usize> {
+        self.instructions
+            .values()
+            .filter_map(|&opcode| jump(opcode)?.1)
+            .collect()
+    }
+
+    // the argument stores right before the return address store, as long as none of them is
+    // jumped to and none reads a slot a later one overwrites
+    fn call_site(&self, jump_address: usize, target: usize) -> CallSite {
+        let targets = self.jump_targets();
+        let mut start = jump_address - 4;
+        let mut args: Vec<(i64, Opcode)> = Vec::new();
+        let mut unknown_arity = false;
+
+        while let Some((address, opcode)) = self.before(start) {
+            let (a, b, slot) = match opcode {
+                Opcode::Add(a, b, Parameter::Relative(slot))
+                | Opcode::Mul(a, b, Parameter::Relative(slot))
+                    if slot > 0 =>
+                {
+                    (a, b, slot)
+                }
+                _ => break,
+            };
+
+            if slot > MAX_ARGS as i64 {
+                unknown_arity = true;
+                break;
+            }
+
+            let later_slot = |parameter| {
+                args.iter()
+                    .any(|&(later, _)| parameter == Parameter::Relative(later))
+            };
+
+            if targets.contains(&start)
+                || later_slot(Parameter::Relative(slot))
+                || later_slot(a)
+                || later_slot(b)
+            {
+                break;
+            }
+
+            args.insert(0, (slot, opcode));
+            start = address;
+        }
+
+        CallSite {
+            start,
+            target,
+            args,
+            unknown_arity,
+        }
+    }
+}
+
+impl Decompiled {
+    fn names(&self, function: &Function) -> Names {
+        // anything else moving the relative base means the slots can't be named statically
+        let moves_base = function.instructions.iter().any(|(&address, &opcode)| {
+            matches!(opcode, Opcode::RelativeBaseOffset(_))
+                && address != function.entry
+                && !self.is_epilogue(function, address)
+        });
+
+        Names {
+            frame: if moves_base { None } else { function.frame },
+            args: function.args,
+        }
+    }
+
+    fn is_epilogue(&self, function: &Function, address: usize) -> bool {
+        function
+            .instructions
+            .get(&(address + 2))
+            .is_some_and(|&opcode| function.returns_at(address + 2, opcode))
+    }
+
+    // comparisons feeding straight into a jump are folded into it
+    fn fused(names: Names, function: &Function, address: usize, opcode: Opcode) -> Option<Item> {
+        let dest = match opcode {
+            Opcode::LessThan(.., dest) | Opcode::Equals(.., dest) => dest,
+            _ => return None,
+        };
+
+        match function.instructions.get(&(address + opcode.len())) {
+            Some(&jump @ Opcode::JumpIfTrue(condition, target))
+            | Some(&jump @ Opcode::JumpIfFalse(condition, target))
+                if condition == dest =>
+            {
+                let negate = matches!(jump, Opcode::JumpIfFalse(..));
+                names.jump(jump, names.expression(opcode, negate)?, target)
+            }
+            _ => None,
+        }
+    }
+
+    fn groups(&self, function: &Function) -> Vec<Group> {
+        let names = self.names(function);
+        let mut special = BTreeMap::new();
+
+        if names.frame.is_some() {
+            special.insert(function.entry, (function.entry, None));
+        }
+
+        for (&address, &opcode) in &function.instructions {
+            if let Some(call) = self.calls.get(&address) {
+                let passed = call.args.last().map_or(0, |&(slot, _)| slot as usize);
+                let args = self.functions.get(&call.target).map_or(0, |f| f.args);
+                let mut values: Vec<_> = (1..=args.max(passed) as i64)
+                    .map(
+                        |slot| match call.args.iter().find(|&&(stored, _)| stored == slot) {
+                            Some(&(_, store)) => names.expression(store, false).unwrap(),
+                            None => names.operand(Parameter::Relative(slot)),
+                        },
+                    )
+                    .collect();
+
+                if call.unknown_arity {
+                    values.push(String::from("..."));
+                }
+
+                let statement = format!("{}({});", name(call.target), values.join(", "));
+
+                special.insert(call.start, (address, Some(Item::Statement(statement))));
+            } else if function.returns_at(address, opcode) {
+                special.insert(address - 2, (address, Some(Item::Return)));
+            } else if let Some(item) = Self::fused(names, function, address, opcode) {
+                special.insert(address, (address + opcode.len(), Some(item)));
+            }
+        }
+
+        let mut groups = Vec::new();
+        let mut covered = 0;
+
+        for (&address, &opcode) in &function.instructions {
+            if address < covered {
+                continue;
+            }
+
+            let (last, item) = match special.get(&address) {
+                Some((last, item)) => (*last, item.clone()),
+                None => (address, names.item(opcode)),
+            };
+            let end = last + function.instructions[&last].len();
+
+            groups.push(Group {
+                start: address,
+                end,
+                item,
+            });
+            covered = end;
+        }
+
+        for &address in &function.invalid {
+            let index = groups.partition_point(|group| group.start < address);
+            groups.insert(
+                index,
+                Group {
+                    start: address,
+                    end: address + 1,
+                    item: Some(Item::Statement(format!(
+                        "// code at {} doesn't decode",
+                        address
+                    ))),
+                },
+            );
+        }
+
+        groups
+    }
+
+    // backward jumps become loops, as long as they nest properly
+    fn loops(groups: &[Group]) -> Vec<Loop> {
+        let starts: BTreeSet<_> = groups.iter().map(|group| group.start).collect();
+        let mut candidates: Vec<_> = groups
+            .iter()
+            .enumerate()
+            .filter_map(|(index, group)| match group.item {
+                Some(Item::Jump(_, target))
+                    if target <= group.start && starts.contains(&target) =>
+                {
+                    Some(Loop {
+                        header: target,
+                        end: group.end,
+                        closing: index,
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        candidates.sort_by_key(|candidate| (candidate.header, std::cmp::Reverse(candidate.end)));
+
+        let mut loops: Vec<Loop> = Vec::new();
+
+        for candidate in candidates {
+            let nests = loops
+                .iter()
+                .all(|outer| candidate.header >= outer.end || candidate.end <= outer.end);
+
+            if nests {
+                loops.push(candidate);
+            }
+        }
+
+        loops
+    }
+
+    fn render(&self, function: &Function, f: &mut fmt::Formatter) -> fmt::Result {
+        let groups = self.groups(function);
+        let loops = Self::loops(&groups);
+        // each line has the address labels below it go in front of it
+        let mut lines: Vec<(usize, usize, String)> = Vec::new();
+        let mut open: Vec<Loop> = Vec::new();
+        let mut labels = BTreeSet::new();
+
+        for (index, group) in groups.iter().enumerate() {
+            for opening in loops.iter().filter(|l| l.header == group.start) {
+                let keyword = match groups[opening.closing].item {
+                    Some(Item::Jump(Flow::If(_), _)) => "do {",
+                    _ => "loop {",
+                };
+
+                lines.push((group.start + 1, open.len() + 1, String::from(keyword)));
+                open.push(*opening);
+            }
+
+            let depth = open.len() + 1;
+            let text = match &group.item {
+                None => continue,
+                Some(Item::Statement(statement)) => statement.clone(),
+                Some(Item::Return) => String::from("return;"),
+                Some(Item::IndirectJump(flow, target)) => match flow {
+                    Flow::Always => format!("goto *{};", target),
+                    Flow::If(condition) => format!("if ({}) goto *{};", condition, target),
+                },
+                Some(Item::Jump(flow, target)) => {
+                    if open.last().is_some_and(|l| l.closing == index) {
+                        let depth = open.len();
+                        let text = match flow {
+                            Flow::Always => String::from("}"),
+                            Flow::If(condition) => format!("}} while ({});", condition),
+                        };
+
+                        lines.push((group.end, depth, text));
+                        open.pop();
+                        continue;
+                    }
+
+                    let falls_through = groups.get(index + 1).map(|next| next.start);
+                    if let (Flow::Always, Some(next)) = (flow, falls_through) {
+                        if next == *target {
+                            continue;
+                        }
+                    }
+
+                    let action = match open.last() {
+                        Some(innermost) if innermost.header == *target => String::from("continue;"),
+                        Some(innermost) if innermost.end == *target => String::from("break;"),
+                        _ => {
+                            labels.insert(*target);
+                            format!("goto L{:04};", target)
+                        }
+                    };
+
+                    match flow {
+                        Flow::Always => action,
+                        Flow::If(condition) => format!("if ({}) {}", condition, action),
+                    }
+                }
+            };
+
+            lines.push((group.end, depth, text));
+        }
+
+        let mut params: Vec<_> = (0..function.args)
+            .map(|arg| format!("arg{}", arg))
+            .collect();
+
+        if function.unknown_arity {
+            params.push(String::from("..."));
+        }
+
+        write!(f, "fn {}({}) {{", name(function.entry), params.join(", "))?;
+
+        match function.frame {
+            Some(frame) => writeln!(f, " // {} word frame", frame)?,
+            None => writeln!(f)?,
+        }
+
+        let mut labeled = BTreeSet::new();
+
+        for (bound, depth, text) in lines {
+            for &label in labels.range(..bound) {
+                if labeled.insert(label) {
+                    writeln!(f, "{:indent$}L{:04}:", "", label, indent = depth * 4 - 2)?;
+                }
+            }
+
+            writeln!(f, "{:indent$}{}", "", text, indent = depth * 4)?;
+        }
+
+        for label in labels.difference(&labeled) {
+            writeln!(f, "    // L{:04} is outside the function", label)?;
+        }
+
+        writeln!(f, "}}")
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &Function> {
+        self.functions.values()
+    }
+}
+
+impl fmt::Display for Decompiled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, function) in self.functions().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+
+            self.render(function, f)?;
+        }
+
+        Ok(())
+    }
+}
+
+// finds the functions called from address 0 onwards and renders them as pseudocode. the
+// relative base slots of functions are named after what they hold: return_address, arg0.. and
+// local0.. below the function's base and out0.. above it, where the calls it makes put their
+// arguments and find their results
+pub fn decompile(program: &[i64]) -> Decompiled {
+    let mut functions = BTreeMap::new();
+    let mut pending = vec![0];
+
+    while let Some(entry) = pending.pop() {
+        functions
+            .entry(entry)
+            .or_insert_with(|| Function::explore(program, entry, &mut pending));
+    }
+
+    let mut calls = BTreeMap::new();
+
+    for function in functions.values() {
+        for (&address, &opcode) in &function.instructions {
+            if let Some(target) = call_target(program, address, opcode) {
+                calls.insert(address, function.call_site(address, target));
+            }
+        }
+    }
+
+    for call in calls.values() {
+        if let Some(callee) = functions.get_mut(&call.target) {
+            let passed = call.args.last().map_or(0, |&(slot, _)| slot as usize);
+            let room = callee.frame.map_or(passed, |frame| frame as usize - 1);
+            callee.args = callee.args.max(passed.min(room));
+            callee.unknown_arity |= call.unknown_arity;
+        }
+    }
+
+    Decompiled { functions, calls }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::asm::assemble, decompile};
+
+    #[test]
+    fn decompiler_recognises_loops_and_calls() {
+        let program = assemble(
+            "
+            RELATIVE_BASE_OFFSET #stack
+            INPUT [n]
+        loop:
+            ADD [sum], [n], [sum]
+            ADD [n], #-1, [n]
+            JUMP_IF_TRUE [n], #loop
+            ADD [sum], #0, rel[1]
+            ADD #return, #0, rel[0]
+            JUMP_IF_TRUE #1, #double
+        return:
+            OUTPUT rel[1]
+            HALT
+        double:
+            RELATIVE_BASE_OFFSET #2
+            MUL rel[-1], #2, rel[-1]
+            RELATIVE_BASE_OFFSET #-2
+            JUMP_IF_TRUE #1, rel[0]
+        n:
+            .data 0
+        sum:
+            .data 0
+        stack:
+        ",
+        )
+        .unwrap();
+
+        let expected = "\
+fn main() {
+    rb += 42;
+    mem[40] = input();
+    do {
+        mem[41] = mem[41] + mem[40];
+        mem[40] = mem[40] - 1;
+    } while (mem[40]);
+    func_0029(mem[41]);
+    output(rb[1]);
+    halt;
+}
+
+fn func_0029(arg0) { // 2 word frame
+    arg0 = arg0 * 2;
+    return;
+}
+";
+        assert_eq!(decompile(&program).to_string(), expected);
+    }
+
+    #[test]
+    fn decompiler_bounds_argument_slots() {
+        // the second store is too far out to be an argument
+        let program = assemble(
+            "
+            RELATIVE_BASE_OFFSET #stack
+            ADD #6, #0, rel[4000000000000000000]
+            ADD #5, #0, rel[1]
+            ADD #return, #0, rel[0]
+            JUMP_IF_TRUE #1, #double
+        return:
+            OUTPUT rel[1]
+            HALT
+        double:
+            RELATIVE_BASE_OFFSET #2
+            MUL rel[-1], #2, rel[-1]
+            RELATIVE_BASE_OFFSET #-2
+            JUMP_IF_TRUE #1, rel[0]
+        stack:
+        ",
+        )
+        .unwrap();
+
+        let expected = "\
+fn main() {
+    rb += 31;
+    rb[4000000000000000000] = 6;
+    func_0020(5, ...);
+    output(rb[1]);
+    halt;
+}
+
+fn func_0020(arg0, ...) { // 2 word frame
+    arg0 = arg0 * 2;
+    return;
+}
+";
+        assert_eq!(decompile(&program).to_string(), expected);
+    }
+}
@@ -0,0 +1,412 @@
+use super::{compiled::CompiledVM, IntcodeError, IntcodeVM, Limit, RunState};
+use std::{collections::BTreeMap, error::Error, fmt};
+
+// random programs loop forever more often than not, so every engine gets cut off after this many
+// instructions
+pub const STEP_BUDGET: u64 = 4096;
+
+const MAX_INSTRUCTIONS: usize = 32;
+const MAX_DATA: usize = 8;
+const MAX_INPUT: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    pub program: Vec<i64>,
+    pub input: Vec<i64>,
+}
+
+// everything a run can be told apart by. the input is all queued up front, so a run ends the
+// first time it halts, asks for more input or fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub outputs: Vec<i64>,
+    pub end: Result<RunState, IntcodeError>,
+    // every non-zero word, in address order
+    pub memory: Vec<(usize, i64)>,
+    pub steps: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub engine: &'static str,
+    pub expected: Outcome,
+    pub found: Outcome,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} disagrees with the reference: expected {:?}, found {:?}",
+            self.engine, self.expected, self.found
+        )
+    }
+}
+
+impl Error for Mismatch {}
+
+// pulls numbers out of arbitrary bytes, running out into zeroes so any input makes a case
+struct Bytes<'a> {
+    data: &'a [u8],
+}
+
+impl Bytes<'_> {
+    fn byte(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((&byte, rest)) => {
+                self.data = rest;
+                byte
+            }
+            None => 0,
+        }
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        self.byte() as usize % bound
+    }
+
+    // mostly small numbers, with the occasional extreme to get arithmetic to overflow
+    fn value(&mut self) -> i64 {
+        match self.byte() % 16 {
+            0 => i64::MAX,
+            1 => i64::MIN,
+            _ => self.byte() as i8 as i64,
+        }
+    }
+}
+
+// builds a program that decodes cleanly up to its first halt: every opcode and parameter mode is
+// valid, writes never go to immediate parameters and jumps mostly land on instructions. it can
+// still jump into data, go negative through the relative base or write over its own code, which
+// is exactly what the engines have to agree on
+pub fn generate(data: &[u8]) -> Case {
+    let mut bytes = Bytes { data };
+    let count = 1 + bytes.below(MAX_INSTRUCTIONS);
+    // opcodes and how many of their parameters are read and written
+    let opcodes: Vec<(i64, usize, usize)> = (0..count)
+        .map(|_| match bytes.below(10) {
+            0 => (1, 2, 1),
+            1 => (2, 2, 1),
+            2 => (3, 0, 1),
+            3 => (4, 1, 0),
+            4 => (5, 2, 0),
+            5 => (6, 2, 0),
+            6 => (7, 2, 1),
+            7 => (8, 2, 1),
+            8 => (9, 1, 0),
+            _ => (99, 0, 0),
+        })
+        .collect();
+
+    let mut starts = Vec::with_capacity(count);
+    let mut code_len = 0;
+    for &(_, reads, writes) in &opcodes {
+        starts.push(code_len as i64);
+        code_len += 1 + reads + writes;
+    }
+
+    let len = code_len + bytes.below(MAX_DATA);
+    let mut program = Vec::with_capacity(len);
+
+    for &(opcode, reads, writes) in &opcodes {
+        let at = program.len();
+        program.push(opcode);
+
+        for parameter in 0..reads + writes {
+            let mode = if parameter < reads {
+                bytes.below(3)
+            } else {
+                // position or relative
+                bytes.below(2) * 2
+            };
+            let jump_target = (opcode == 5 || opcode == 6) && parameter == 1;
+
+            // relative offsets and base adjustments lean positive, or most programs would do
+            // nothing but fail on a negative address
+            program.push(match mode {
+                0 => bytes.below(len + 4) as i64,
+                1 if jump_target => starts[bytes.below(count)],
+                1 if opcode == 9 => bytes.below(16) as i64 - 4,
+                1 => bytes.value(),
+                _ => bytes.below(len + 4) as i64 - 4,
+            });
+            program[at] += mode as i64 * 10i64.pow(parameter as u32 + 2);
+        }
+    }
+
+    while program.len() < len {
+        program.push(bytes.value());
+    }
+
+    let input = (0..bytes.below(MAX_INPUT + 1))
+        .map(|_| bytes.value())
+        .collect();
+
+    Case { program, input }
+}
+
+// the instruction set written out as plainly as possible, to check the real engines against
+struct Reference {
+    memory: BTreeMap<usize, i64>,
+    ip: usize,
+    base: i64,
+    instruction: i64,
+    modes: Vec<i64>,
+}
+
+enum Step {
+    Next(usize),
+    Output(i64),
+    End(RunState),
+}
+
+impl Reference {
+    fn word(&self, index: usize) -> i64 {
+        self.memory.get(&index).copied().unwrap_or(0)
+    }
+
+    fn address(&self, parameter: usize) -> Result<usize, IntcodeError> {
+        let (ip, instruction) = (self.ip, self.instruction);
+        let value = self.word(ip + 1 + parameter);
+        let address = match self.modes[parameter] {
+            0 => value,
+            2 => self.base.saturating_add(value),
+            _ => return Err(IntcodeError::WriteToImmediate { ip, instruction }),
+        };
+
+        if address < 0 {
+            Err(IntcodeError::NegativeAddress {
+                ip,
+                instruction,
+                address,
+            })
+        } else {
+            Ok(address as usize)
+        }
+    }
+
+    fn load(&self, parameter: usize) -> Result<i64, IntcodeError> {
+        if self.modes[parameter] == 1 {
+            Ok(self.word(self.ip + 1 + parameter))
+        } else {
+            Ok(self.word(self.address(parameter)?))
+        }
+    }
+
+    fn store(&mut self, parameter: usize, value: i64) -> Result<(), IntcodeError> {
+        let address = self.address(parameter)?;
+        self.memory.insert(address, value);
+        Ok(())
+    }
+
+    fn step(&mut self, input: &mut dyn Iterator<Item = i64>) -> Result<Step, IntcodeError> {
+        let (ip, instruction) = (self.ip, self.word(self.ip));
+        let opcode = instruction % 100;
+        let count = match opcode {
+            1 | 2 | 7 | 8 => 3,
+            5 | 6 => 2,
+            3 | 4 | 9 => 1,
+            99 => 0,
+            _ => return Err(IntcodeError::UnknownOpcode { ip, instruction }),
+        };
+
+        self.instruction = instruction;
+        self.modes = (0..count)
+            .map(|parameter| instruction / 10i64.pow(parameter + 2) % 10)
+            .collect();
+
+        // every mode is checked before anything is executed
+        if let Some(&mode) = self.modes.iter().find(|&&mode| mode > 2) {
+            return Err(IntcodeError::InvalidMode {
+                ip,
+                instruction,
+                mode,
+            });
+        }
+
+        let next = ip + 1 + count as usize;
+
+        match opcode {
+            1 => self.store(2, self.load(0)?.wrapping_add(self.load(1)?))?,
+            2 => self.store(2, self.load(0)?.wrapping_mul(self.load(1)?))?,
+            7 => self.store(2, (self.load(0)? < self.load(1)?) as i64)?,
+            8 => self.store(2, (self.load(0)? == self.load(1)?) as i64)?,
+            3 => {
+                let address = self.address(0)?;
+
+                match input.next() {
+                    Some(value) => {
+                        self.memory.insert(address, value);
+                    }
+                    None => return Ok(Step::End(RunState::NeedsInput)),
+                }
+            }
+            4 => return Ok(Step::Output(self.load(0)?)),
+            5 | 6 if (self.load(0)? != 0) == (opcode == 5) => {
+                let target = self.load(1)?;
+
+                if target < 0 {
+                    return Err(IntcodeError::NegativeAddress {
+                        ip,
+                        instruction,
+                        address: target,
+                    });
+                }
+
+                return Ok(Step::Next(target as usize));
+            }
+            5 | 6 => (),
+            9 => self.base = self.base.saturating_add(self.load(0)?),
+            _ => return Ok(Step::End(RunState::Halted)),
+        }
+
+        Ok(Step::Next(next))
+    }
+}
+
+pub fn reference(case: &Case, budget: u64) -> Outcome {
+    let mut machine = Reference {
+        memory: case.program.iter().copied().enumerate().collect(),
+        ip: 0,
+        base: 0,
+        instruction: 0,
+        modes: Vec::new(),
+    };
+    let mut input = case.input.iter().copied();
+    let mut outputs = Vec::new();
+    let mut steps = 0;
+
+    let end = loop {
+        if steps == budget {
+            break Err(IntcodeError::BudgetExhausted {
+                ip: machine.ip,
+                instruction: machine.word(machine.ip),
+                limit: Limit::Steps,
+            });
+        }
+
+        match machine.step(&mut input) {
+            Ok(Step::Next(next)) => machine.ip = next,
+            Ok(Step::Output(value)) => {
+                outputs.push(value);
+                machine.ip += 2;
+            }
+            Ok(Step::End(RunState::NeedsInput)) => break Ok(RunState::NeedsInput),
+            Ok(Step::End(state)) => {
+                steps += 1;
+                break Ok(state);
+            }
+            Err(error) => break Err(error),
+        }
+
+        steps += 1;
+    };
+
+    Outcome {
+        outputs,
+        end,
+        memory: machine
+            .memory
+            .into_iter()
+            .filter(|&(_, value)| value != 0)
+            .collect(),
+        steps,
+    }
+}
+
+fn finish<F>(mut run: F) -> (Vec<i64>, Result<RunState, IntcodeError>)
+where
+    F: FnMut() -> Result<RunState, IntcodeError>,
+{
+    let mut outputs = Vec::new();
+
+    loop {
+        match run() {
+            Ok(RunState::Output(value)) => outputs.push(value),
+            end => break (outputs, end),
+        }
+    }
+}
+
+fn nonzero(cells: Vec<(usize, i64)>) -> Vec<(usize, i64)> {
+    cells.into_iter().filter(|&(_, value)| value != 0).collect()
+}
+
+pub fn interpreted(case: &Case, budget: u64, cache_decoded: bool) -> Outcome {
+    let mut vm = IntcodeVM::new(case.program.clone());
+    vm.set_decode_cache(cache_decoded);
+    vm.set_step_budget(Some(budget));
+    for &value in &case.input {
+        vm.input(value);
+    }
+
+    let (outputs, end) = finish(|| vm.run());
+    Outcome {
+        outputs,
+        end,
+        memory: nonzero(vm.memory.backend.cells()),
+        steps: vm.steps(),
+    }
+}
+
+pub fn compiled(case: &Case, budget: u64) -> Outcome {
+    let mut vm = CompiledVM::new(case.program.clone());
+    vm.set_step_budget(Some(budget));
+    for &value in &case.input {
+        vm.input(value);
+    }
+
+    let (outputs, end) = finish(|| vm.run());
+    Outcome {
+        outputs,
+        end,
+        memory: nonzero(vm.cells()),
+        steps: vm.steps(),
+    }
+}
+
+// runs the case on every engine and returns what they all agreed on
+pub fn check(case: &Case, budget: u64) -> Result<Outcome, Box<Mismatch>> {
+    let expected = reference(case, budget);
+    let engines = [
+        ("IntcodeVM", interpreted(case, budget, false)),
+        (
+            "IntcodeVM with the decode cache",
+            interpreted(case, budget, true),
+        ),
+        ("CompiledVM", compiled(case, budget)),
+    ];
+
+    for (engine, found) in engines.iter() {
+        if *found != expected {
+            return Err(Box::new(Mismatch {
+                engine,
+                expected,
+                found: found.clone(),
+            }));
+        }
+    }
+
+    Ok(expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, generate, STEP_BUDGET};
+    use proptest::{collection::vec, prelude::*};
+
+    proptest! {
+        // self-modifying code only gets compiled before it's patched every so often, so this
+        // needs more cases than the default to reach it
+        #![proptest_config(ProptestConfig::with_cases(4096))]
+
+        #[test]
+        fn engines_agree_on_random_programs(data in vec(any::<u8>(), 0..512)) {
+            let case = generate(&data);
+
+            if let Err(mismatch) = check(&case, STEP_BUDGET) {
+                prop_assert!(false, "{}\nin {:?}", mismatch, case);
+            }
+        }
+    }
+}
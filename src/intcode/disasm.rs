@@ -0,0 +1,163 @@
+use super::{Opcode, Parameter};
+use std::fmt;
+
+// how wide the raw words column is before the decoded instruction starts
+const WORDS_WIDTH: usize = 28;
+// how many consecutive data words are grouped on a single line
+const DATA_WORDS_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub address: usize,
+    pub words: Vec<i64>,
+    instruction: Option<Opcode>,
+}
+
+impl Line {
+    pub fn is_data(&self) -> bool {
+        self.instruction.is_none()
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        self.instruction.map_or("DATA", Opcode::mnemonic)
+    }
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Position(value) => write!(f, "[{}]", value),
+            Self::Immediate(value) => write!(f, "#{}", value),
+            Self::Relative(value) => write!(f, "rel[{}]", value),
+        }
+    }
+}
+
+impl Opcode {
+    // the instruction under another name, for registered opcodes that only the VM knows the
+    // names of
+    pub(super) fn listing(self, mnemonic: &str) -> String {
+        let parameters: Vec<_> = self
+            .parameters()
+            .into_iter()
+            .map(|parameter| parameter.to_string())
+            .collect();
+
+        if parameters.is_empty() {
+            mnemonic.to_string()
+        } else {
+            format!("{} {}", mnemonic, parameters.join(", "))
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.listing(self.mnemonic()))
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let words = self
+            .words
+            .iter()
+            .map(|word| word.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        write!(
+            f,
+            "{:04}: {:<width$} ",
+            self.address,
+            words,
+            width = WORDS_WIDTH
+        )?;
+
+        match self.instruction {
+            Some(opcode) => write!(f, "{}", opcode),
+            None => write!(f, "DATA {}", words.replace(' ', ", ")),
+        }
+    }
+}
+
+// the instruction at the address, if it decodes and fits inside the program
+pub(super) fn decode_at(program: &[i64], address: usize) -> Option<Opcode> {
+    Opcode::decode(address, |index| program.get(index).copied().unwrap_or(0))
+        .ok()
+        .filter(|opcode| address + opcode.len() <= program.len())
+}
+
+// a plain linear sweep; any word that doesn't decode into a complete instruction inside the
+// program is treated as data
+pub fn disassemble(program: &[i64]) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+    let mut address = 0;
+
+    while address < program.len() {
+        match decode_at(program, address) {
+            Some(opcode) => {
+                let len = opcode.len();
+                lines.push(Line {
+                    address,
+                    words: program[address..address + len].to_vec(),
+                    instruction: Some(opcode),
+                });
+                address += len;
+            }
+            None => {
+                match lines.last_mut() {
+                    Some(line) if line.is_data() && line.words.len() < DATA_WORDS_PER_LINE => {
+                        line.words.push(program[address])
+                    }
+                    _ => lines.push(Line {
+                        address,
+                        words: vec![program[address]],
+                        instruction: None,
+                    }),
+                }
+                address += 1;
+            }
+        }
+    }
+
+    lines
+}
+
+pub fn listing(program: &[i64]) -> String {
+    disassemble(program)
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, listing};
+
+    #[test]
+    fn disassembly_listing_format() {
+        // the ADD at the end would run past the program, so it's listed as data
+        let program = vec![1101, 12, 5, 3, 204, -3, 42, 0, 99, 1, 2];
+
+        assert_eq!(
+            listing(&program),
+            "0000: 1101 12 5 3                  ADD #12, #5, [3]\n\
+             0004: 204 -3                       OUTPUT rel[-3]\n\
+             0006: 42 0                         DATA 42, 0\n\
+             0008: 99                           HALT\n\
+             0009: 1 2                          DATA 1, 2\n"
+        );
+
+        let lines = disassemble(&program);
+        let mnemonics: Vec<_> = lines.iter().map(|line| line.mnemonic()).collect();
+        assert_eq!(mnemonics, vec!["ADD", "OUTPUT", "DATA", "HALT", "DATA"]);
+        assert!(lines[2].is_data());
+        assert_eq!(lines[4].words, vec![1, 2]);
+
+        // long runs of data are split over several lines
+        let lines = disassemble(&[0; 9]);
+        assert_eq!(lines.len(), 2);
+        assert_eq!((lines[1].address, lines[1].words.len()), (8, 1));
+    }
+}
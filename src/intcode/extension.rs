@@ -0,0 +1,395 @@
+use super::{
+    address, Fault, IntcodeError, IntcodeVM, Memory, Opcode, OpcodeOutput, Parameter,
+    MAX_INSTRUCTION_LEN,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt,
+    ops::RangeInclusive,
+};
+
+// everything below is taken by the built in opcodes, and 99 is halt. anything past two digits
+// would run into the parameter modes
+const NUMBERS: RangeInclusive<i64> = 10..=98;
+// writes only throw away cached instructions that start close enough to include the written word,
+// so registered instructions can't be any longer than the built in ones
+pub const MAX_PARAMETERS: usize = MAX_INSTRUCTION_LEN - 1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegisterError {
+    Reserved(i64),
+    AlreadyRegistered(i64),
+    TooManyParameters { number: i64, parameters: usize },
+}
+
+// what to do after a registered instruction's handler has run
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Effect {
+    Continue,
+    Output(i64),
+    Jump(i64),
+    // the instruction is run again once there's input, so the handler shouldn't have changed
+    // anything before asking for it
+    NeedsInput,
+    Halt,
+}
+
+type Handler = Box<dyn FnMut(&mut Operands) -> Result<Effect, IntcodeError> + Send>;
+
+struct Extension {
+    name: &'static str,
+    parameters: usize,
+    handler: Handler,
+}
+
+#[derive(Default)]
+pub(super) struct Extensions {
+    opcodes: HashMap<i64, Extension>,
+}
+
+// a decoded registered instruction, only the first count parameters are used. it's kept as small
+// as the built in instructions so the decode cache doesn't get any slower, which is why the name
+// has to be looked up from the registry
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Custom {
+    pub(super) number: u8,
+    pub(super) count: u8,
+    pub(super) parameters: [Parameter; MAX_PARAMETERS],
+}
+
+// what a handler gets to work with: the instruction's parameters, the rest of memory and the
+// input queue. asking for a parameter past the registered count fails the run with BadOperand
+pub struct Operands<'a> {
+    memory: &'a mut Memory,
+    input: &'a mut VecDeque<i64>,
+    rel_base_offset: i64,
+    ip: usize,
+    instruction: i64,
+    parameters: &'a [Parameter],
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Reserved(number) => write!(
+                f,
+                "opcode {} is reserved, registered opcodes have to be between {} and {}",
+                number,
+                NUMBERS.start(),
+                NUMBERS.end()
+            ),
+            Self::AlreadyRegistered(number) => {
+                write!(f, "opcode {} is already registered", number)
+            }
+            Self::TooManyParameters { number, parameters } => write!(
+                f,
+                "opcode {} takes {} parameters, at most {} are supported",
+                number, parameters, MAX_PARAMETERS
+            ),
+        }
+    }
+}
+
+impl Error for RegisterError {}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.opcodes
+                    .iter()
+                    .map(|(number, extension)| (number, extension.name)),
+            )
+            .finish()
+    }
+}
+
+impl Custom {
+    pub(super) fn parameters(&self) -> &[Parameter] {
+        &self.parameters[..self.count as usize]
+    }
+}
+
+impl Operands<'_> {
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn count(&self) -> usize {
+        self.parameters.len()
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.rel_base_offset
+    }
+
+    fn fault(&self, fault: Fault) -> IntcodeError {
+        fault.at(self.ip, self.instruction)
+    }
+
+    fn parameter(&self, parameter: usize) -> Result<Parameter, IntcodeError> {
+        self.parameters
+            .get(parameter)
+            .copied()
+            .ok_or_else(|| self.fault(Fault::BadOperand(parameter)))
+    }
+
+    pub fn read(&mut self, parameter: usize) -> Result<i64, IntcodeError> {
+        self.parameter(parameter)?
+            .evaluate(self.memory, self.rel_base_offset)
+            .map_err(|fault| self.fault(fault))
+    }
+
+    pub fn write(&mut self, parameter: usize, value: i64) -> Result<(), IntcodeError> {
+        let index = self
+            .parameter(parameter)?
+            .position(self.rel_base_offset)
+            .map_err(|fault| self.fault(fault))?;
+        self.memory.set(index, value);
+        Ok(())
+    }
+
+    // any word in memory, for handlers that work on more than their parameters. watchpoints and
+    // traces see these the same as parameter accesses
+    pub fn load(&mut self, address: usize) -> i64 {
+        self.memory.get(address)
+    }
+
+    pub fn store(&mut self, address: usize, value: i64) {
+        self.memory.set(address, value);
+    }
+
+    pub fn input(&mut self) -> Option<i64> {
+        self.input.pop_front()
+    }
+}
+
+impl Extensions {
+    // how many parameters a registered opcode takes
+    pub(super) fn parameters(&self, number: i64) -> Option<usize> {
+        self.opcodes
+            .get(&number)
+            .map(|extension| extension.parameters)
+    }
+
+    // what to call an instruction, including registered ones
+    pub(super) fn mnemonic(&self, opcode: Opcode) -> &'static str {
+        match opcode {
+            Opcode::Custom(custom) => self
+                .opcodes
+                .get(&i64::from(custom.number))
+                .map_or(opcode.mnemonic(), |extension| extension.name),
+            _ => opcode.mnemonic(),
+        }
+    }
+
+    pub(super) fn execute(
+        &mut self,
+        custom: Custom,
+        memory: &mut Memory,
+        input: &mut VecDeque<i64>,
+        rel_base_offset: i64,
+        ip: usize,
+    ) -> Result<OpcodeOutput, IntcodeError> {
+        let instruction = memory.fetch(ip);
+        // decoding only produces opcodes that are registered, and the decode cache is cleared
+        // whenever one is removed, so this is only unknown if something else went wrong
+        let extension = self
+            .opcodes
+            .get_mut(&i64::from(custom.number))
+            .ok_or_else(|| Fault::UnknownOpcode.at(ip, instruction))?;
+        let mut operands = Operands {
+            memory,
+            input,
+            rel_base_offset,
+            ip,
+            instruction,
+            parameters: custom.parameters(),
+        };
+
+        Ok(match (extension.handler)(&mut operands)? {
+            Effect::Continue => OpcodeOutput::None,
+            Effect::Output(value) => OpcodeOutput::Output(value),
+            Effect::Jump(target) => {
+                OpcodeOutput::Jump(address(target).map_err(|fault| fault.at(ip, instruction))?)
+            }
+            Effect::NeedsInput => OpcodeOutput::NeedsInput,
+            Effect::Halt => OpcodeOutput::Halt,
+        })
+    }
+}
+
+impl IntcodeVM {
+    // adds an instruction to this VM. the handler is called every time it's executed, after the
+    // parameters have been decoded with the usual modes. the name is what disassemblies and
+    // profiles show for it
+    pub fn register_opcode<F>(
+        &mut self,
+        number: i64,
+        name: &'static str,
+        parameters: usize,
+        handler: F,
+    ) -> Result<(), RegisterError>
+    where
+        F: FnMut(&mut Operands) -> Result<Effect, IntcodeError> + Send + 'static,
+    {
+        if !NUMBERS.contains(&number) {
+            return Err(RegisterError::Reserved(number));
+        }
+
+        if parameters > MAX_PARAMETERS {
+            return Err(RegisterError::TooManyParameters { number, parameters });
+        }
+
+        if self.extensions.opcodes.contains_key(&number) {
+            return Err(RegisterError::AlreadyRegistered(number));
+        }
+
+        self.extensions.opcodes.insert(
+            number,
+            Extension {
+                name,
+                parameters,
+                handler: Box::new(handler),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn unregister_opcode(&mut self, number: i64) -> bool {
+        let removed = self.extensions.opcodes.remove(&number).is_some();
+
+        if removed {
+            self.memory.decoded.clear();
+        }
+
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{debugger::Debugger, tests::outputs, Fault, IntcodeError, IntcodeVM},
+        Effect, RegisterError,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn registered_opcodes_run_handlers() {
+        // PRINT #7, SQUARE [10], [11], OUTPUT [11], HALT
+        let program = vec![142, 7, 43, 10, 11, 4, 11, 99, 0, 0, 9, 0];
+        let printed = Arc::new(Mutex::new(Vec::new()));
+        let mut vm = IntcodeVM::new(program.clone());
+
+        let log = Arc::clone(&printed);
+        vm.register_opcode(42, "PRINT", 1, move |operands| {
+            log.lock().unwrap().push(operands.read(0)?);
+            Ok(Effect::Continue)
+        })
+        .unwrap();
+        vm.register_opcode(43, "SQUARE", 2, |operands| {
+            let value = operands.read(0)?;
+            operands.write(1, value * value)?;
+            Ok(Effect::Continue)
+        })
+        .unwrap();
+
+        assert_eq!(
+            vm.register_opcode(5, "JUMP", 2, |_| Ok(Effect::Continue)),
+            Err(RegisterError::Reserved(5))
+        );
+        assert_eq!(
+            vm.register_opcode(42, "PRINT", 1, |_| Ok(Effect::Continue)),
+            Err(RegisterError::AlreadyRegistered(42))
+        );
+        assert_eq!(
+            vm.register_opcode(44, "WIDE", 4, |_| Ok(Effect::Continue)),
+            Err(RegisterError::TooManyParameters {
+                number: 44,
+                parameters: 4
+            })
+        );
+
+        let debugger = Debugger::new(vm);
+        assert_eq!(
+            debugger.instruction_at(2),
+            (String::from("SQUARE [10], [11]"), 3)
+        );
+
+        let mut vm = debugger.into_inner();
+        assert_eq!(outputs(&mut vm), Ok(vec![81]));
+        assert_eq!(*printed.lock().unwrap(), vec![7]);
+
+        let mut vm = IntcodeVM::new(program);
+        vm.register_opcode(42, "PRINT", 1, |_| Ok(Effect::Continue))
+            .unwrap();
+        assert_eq!(
+            vm.run(),
+            Err(IntcodeError::UnknownOpcode {
+                ip: 2,
+                instruction: 43
+            })
+        );
+        assert!(vm.unregister_opcode(42));
+        assert!(!vm.unregister_opcode(42));
+    }
+
+    #[test]
+    fn handlers_asking_for_missing_parameters_fail_the_run() {
+        // PRINT #7, HALT
+        let mut vm = IntcodeVM::new(vec![142, 7, 99]);
+        vm.register_opcode(42, "PRINT", 1, |operands| {
+            operands.read(1)?;
+            Ok(Effect::Continue)
+        })
+        .unwrap();
+        assert_eq!(
+            vm.run(),
+            Err(IntcodeError::BadOperand {
+                ip: 0,
+                instruction: 142,
+                operand: 1
+            })
+        );
+
+        let mut vm = IntcodeVM::new(vec![142, 7, 99]);
+        vm.register_opcode(42, "PRINT", 1, |operands| {
+            operands.write(usize::MAX, 0)?;
+            Ok(Effect::Continue)
+        })
+        .unwrap();
+        assert_eq!(
+            vm.run(),
+            Err(IntcodeError::BadOperand {
+                ip: 0,
+                instruction: 142,
+                operand: usize::MAX
+            })
+        );
+    }
+
+    #[test]
+    fn custom_opcodes_without_a_handler_are_unknown() {
+        let mut vm = IntcodeVM::new(vec![142, 7, 99]);
+        vm.register_opcode(42, "PRINT", 1, |_| Ok(Effect::Continue))
+            .unwrap();
+
+        // an instruction decoded while its handler was still there
+        let opcode = vm.decode(0).unwrap();
+        assert!(vm.unregister_opcode(42));
+
+        assert_eq!(
+            vm.execute(0, 0, opcode).err(),
+            Some(IntcodeError::UnknownOpcode {
+                ip: 0,
+                instruction: 142,
+            })
+        );
+        assert!(matches!(
+            opcode.execute(&mut vm.memory, &mut vm.input, 0),
+            Err(Fault::UnknownOpcode)
+        ));
+    }
+}
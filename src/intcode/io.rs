@@ -0,0 +1,410 @@
+use super::{IntcodeError, IntcodeVM, RunState};
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, Write},
+    sync::mpsc::{Receiver, Sender},
+};
+
+// returning None means there's nothing to read right now, the VM then pauses with NeedsInput
+pub trait InputDevice {
+    fn read(&mut self) -> Option<i64>;
+}
+
+pub trait OutputDevice {
+    fn write(&mut self, value: i64);
+}
+
+// reads one integer per line, skipping empty lines
+#[derive(Debug)]
+pub struct TextInput<R> {
+    reader: R,
+    error: Option<io::Error>,
+}
+
+// writes one integer per line
+#[derive(Debug)]
+pub struct TextOutput<W> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl InputDevice for VecDeque<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+}
+
+impl OutputDevice for VecDeque<i64> {
+    fn write(&mut self, value: i64) {
+        self.push_back(value);
+    }
+}
+
+impl OutputDevice for Vec<i64> {
+    fn write(&mut self, value: i64) {
+        self.push(value);
+    }
+}
+
+impl<F: FnMut() -> Option<i64>> InputDevice for F {
+    fn read(&mut self) -> Option<i64> {
+        self()
+    }
+}
+
+impl<F: FnMut(i64)> OutputDevice for F {
+    fn write(&mut self, value: i64) {
+        self(value)
+    }
+}
+
+// blocks until a value arrives, so the sending side can be another thread
+impl InputDevice for Receiver<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+// values sent after the receiver is gone are dropped
+impl OutputDevice for Sender<i64> {
+    fn write(&mut self, value: i64) {
+        let _ = self.send(value);
+    }
+}
+
+// reading from another VM runs it until its next output. if it halts, needs input or fails,
+// there's nothing to read, and a failure is kept for device_error
+impl InputDevice for IntcodeVM {
+    fn read(&mut self) -> Option<i64> {
+        if self.device_error.is_some() {
+            return None;
+        }
+
+        loop {
+            match self.run() {
+                Ok(RunState::Output(value)) => break Some(value),
+                Ok(RunState::Watchpoint(_)) => (),
+                Ok(_) => break None,
+                Err(e) => {
+                    self.device_error = Some(e);
+                    break None;
+                }
+            }
+        }
+    }
+}
+
+// writing to another VM queues the value as its input
+impl OutputDevice for IntcodeVM {
+    fn write(&mut self, value: i64) {
+        self.input(value);
+    }
+}
+
+impl<R: BufRead> TextInput<R> {
+    pub fn new(reader: R) -> Self {
+        TextInput {
+            reader,
+            error: None,
+        }
+    }
+
+    // the I/O or parse error that ended the input, if any
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+}
+
+impl<R: BufRead> InputDevice for TextInput<R> {
+    fn read(&mut self) -> Option<i64> {
+        if self.error.is_some() {
+            return None;
+        }
+
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+
+            match self.reader.read_line(&mut line) {
+                Ok(0) => break None,
+                Ok(_) if line.trim().is_empty() => (),
+                Ok(_) => match line.trim().parse() {
+                    Ok(value) => break Some(value),
+                    Err(e) => {
+                        self.error = Some(io::Error::new(io::ErrorKind::InvalidData, e));
+                        break None;
+                    }
+                },
+                Err(e) => {
+                    self.error = Some(e);
+                    break None;
+                }
+            }
+        }
+    }
+}
+
+impl<W: Write> TextOutput<W> {
+    pub fn new(writer: W) -> Self {
+        TextOutput {
+            writer,
+            error: None,
+        }
+    }
+
+    // flushes the writer and returns it, or the first error writing to it
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.error {
+            Some(e) => Err(e),
+            None => {
+                self.writer.flush()?;
+                Ok(self.writer)
+            }
+        }
+    }
+}
+
+impl<W: Write> OutputDevice for TextOutput<W> {
+    fn write(&mut self, value: i64) {
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.writer, "{}", value) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+impl IntcodeVM {
+    // the error that stopped this VM while another was reading from it, if any
+    pub fn device_error(&self) -> Option<&IntcodeError> {
+        self.device_error.as_ref()
+    }
+
+    // runs until the VM halts, hits a watchpoint, or needs input that neither the queue nor the
+    // input device has. every output goes to the output device
+    pub fn run_with<I, O>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<RunState, IntcodeError>
+    where
+        I: InputDevice + ?Sized,
+        O: OutputDevice + ?Sized,
+    {
+        loop {
+            match self.run()? {
+                RunState::Output(value) => output.write(value),
+                RunState::NeedsInput => match input.read() {
+                    Some(value) => self.input(value),
+                    None => break Ok(RunState::NeedsInput),
+                },
+                state => break Ok(state),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            tests::{vm, BrokenReader, Recorder},
+            IntcodeError, IntcodeVM, RunState,
+        },
+        InputDevice, OutputDevice, TextInput, TextOutput,
+    };
+    use std::{
+        collections::VecDeque,
+        io::{self, BufReader, Cursor},
+        sync::mpsc,
+    };
+
+    fn echo() -> IntcodeVM {
+        vm("
+        loop:
+            INPUT [value]
+            OUTPUT [value]
+            JUMP_IF_TRUE #1, #loop
+        value:
+            .data 0
+        ")
+    }
+
+    #[test]
+    fn text_input_reads_a_number_per_line() {
+        let mut input = TextInput::new(Cursor::new("1\n\n -2 \n\n3"));
+
+        assert_eq!(input.read(), Some(1));
+        assert_eq!(input.read(), Some(-2));
+        assert_eq!(input.read(), Some(3));
+        assert_eq!(input.read(), None);
+        assert!(input.error().is_none());
+    }
+
+    #[test]
+    fn text_input_stops_at_the_first_bad_line() {
+        let mut input = TextInput::new(Cursor::new("4\nfour\n5\n"));
+
+        assert_eq!(input.read(), Some(4));
+        assert_eq!(input.read(), None);
+        assert_eq!(input.error().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        // the line after it is never read
+        assert_eq!(input.read(), None);
+    }
+
+    #[test]
+    fn text_input_keeps_read_errors() {
+        let mut input = TextInput::new(BufReader::new(BrokenReader));
+
+        assert_eq!(input.read(), None);
+        assert_eq!(input.error().unwrap().to_string(), "unreadable");
+    }
+
+    #[test]
+    fn text_output_writes_a_number_per_line() {
+        let mut output = TextOutput::new(Recorder::default());
+        output.write(1);
+        output.write(-20);
+
+        let recorder = output.finish().unwrap();
+        assert_eq!(recorder.written, b"1\n-20\n");
+        assert_eq!(recorder.flushes, 1);
+    }
+
+    #[test]
+    fn text_output_finish_returns_the_first_error() {
+        let mut output = TextOutput::new(Recorder::broken());
+        output.write(1);
+        output.write(2);
+
+        assert_eq!(output.finish().unwrap_err().to_string(), "write 1 failed");
+    }
+
+    #[test]
+    fn channels_carry_values_between_threads() {
+        let (to_vm, mut from_outside) = mpsc::channel();
+        let (mut to_outside, from_vm) = mpsc::channel();
+
+        let feeder = std::thread::spawn(move || {
+            for &value in &[3, 1, 4] {
+                to_vm.send(value).unwrap();
+            }
+        });
+
+        // the input only runs dry once the sending side is dropped
+        assert_eq!(
+            echo().run_with(&mut from_outside, &mut to_outside),
+            Ok(RunState::NeedsInput)
+        );
+        feeder.join().unwrap();
+        assert_eq!(from_vm.try_iter().collect::<Vec<_>>(), vec![3, 1, 4]);
+
+        // with nobody receiving, outputs are dropped
+        drop(from_vm);
+        to_outside.write(5);
+    }
+
+    #[test]
+    fn closures_and_queues_are_devices() {
+        let mut values = vec![7, 8].into_iter();
+        let mut seen = Vec::new();
+
+        assert_eq!(
+            echo().run_with(&mut || values.next(), &mut |value| seen.push(value)),
+            Ok(RunState::NeedsInput)
+        );
+        assert_eq!(seen, vec![7, 8]);
+
+        let mut queue: VecDeque<i64> = vec![9].into_iter().collect();
+        let mut collected = VecDeque::new();
+        assert_eq!(
+            echo().run_with(&mut queue, &mut collected),
+            Ok(RunState::NeedsInput)
+        );
+        assert_eq!(collected, vec![9]);
+    }
+
+    #[test]
+    fn long_runs_agree_with_the_decode_cache_on_and_off() {
+        // enough inputs to run well past the cache's warmup
+        let inputs: Vec<i64> = (1..=1000).collect();
+        let mut results = Vec::new();
+
+        for &enabled in &[true, false] {
+            let mut vm = echo();
+            let mut values = inputs.iter().copied();
+            let mut seen = Vec::new();
+
+            vm.set_decode_cache(enabled);
+            assert_eq!(
+                vm.run_with(&mut || values.next(), &mut seen),
+                Ok(RunState::NeedsInput)
+            );
+            results.push((seen, vm.steps()));
+        }
+
+        assert_eq!(results[0], (inputs, 3000));
+        assert_eq!(results[0], results[1]);
+    }
+
+    #[test]
+    fn devices_chain_two_vms() {
+        let doubler = "
+        loop:
+            INPUT [value]
+            MUL [value], #2, [value]
+            OUTPUT [value]
+            JUMP_IF_TRUE #1, #loop
+        value:
+            .data 0
+        ";
+        let mut first = vm(doubler);
+        let mut second = vm(doubler);
+        let mut third = vm(doubler);
+        let mut source = vec![2, 3].into_iter();
+        let mut sink = Vec::new();
+
+        // first pushes into second's input queue, third pulls outputs out of second
+        first.input(1);
+        assert_eq!(
+            first.run_with(&mut || source.next(), &mut second),
+            Ok(RunState::NeedsInput)
+        );
+        assert_eq!(
+            third.run_with(&mut second, &mut sink),
+            Ok(RunState::NeedsInput)
+        );
+        assert_eq!(sink, vec![8, 16, 24]);
+    }
+
+    #[test]
+    fn failing_upstream_vm_keeps_its_error() {
+        // outputs 7, then runs into an unknown opcode
+        let mut upstream = IntcodeVM::new(vec![104, 7, 42]);
+        let mut downstream = vm("
+            INPUT [value]
+            OUTPUT [value]
+            INPUT [value]
+            OUTPUT [value]
+            HALT
+        value:
+            .data 0
+        ");
+        let mut sink = Vec::new();
+
+        assert_eq!(
+            downstream.run_with(&mut upstream, &mut sink),
+            Ok(RunState::NeedsInput)
+        );
+        assert_eq!(sink, vec![7]);
+        assert_eq!(
+            upstream.device_error(),
+            Some(&IntcodeError::UnknownOpcode {
+                ip: 2,
+                instruction: 42,
+            })
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use std::{collections::HashMap, fmt};
+
+const PAGE_SIZE: usize = 1024;
+// pages below this are found by indexing a vector instead of hashing, which keeps the usual
+// stack and heap accesses right past the program image fast
+const LOW_PAGES: usize = 4096;
+
+// words that were never written read as zero
+pub trait MemoryBackend: fmt::Debug + Send {
+    fn get(&self, index: usize) -> i64;
+    fn set(&mut self, index: usize, value: i64);
+    // every word that might be non-zero, in address order
+    fn cells(&self) -> Vec<(usize, i64)>;
+    // one past the highest address there's storage for
+    fn high_water(&self) -> usize;
+    fn clone_box(&self) -> Box<dyn MemoryBackend>;
+}
+
+// grows a vector up to the highest address touched
+#[derive(Debug, Clone, Default)]
+pub struct DenseMemory {
+    memory: Vec<i64>,
+}
+
+// the program image in a vector, everything past it in pages allocated on first write
+#[derive(Debug, Clone, Default)]
+pub struct SparseMemory {
+    image: Vec<i64>,
+    low_pages: Vec<Option<Box<[i64]>>>,
+    pages: HashMap<usize, Box<[i64]>>,
+}
+
+// what a VM actually holds, the default sparse memory is kept unboxed so the interpreter's reads
+// and writes to it can be inlined
+#[derive(Debug, Clone)]
+pub(super) enum Backend {
+    Sparse(SparseMemory),
+    Boxed(Box<dyn MemoryBackend>),
+}
+
+impl Clone for Box<dyn MemoryBackend> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+impl DenseMemory {
+    pub fn new(memory: Vec<i64>) -> Self {
+        DenseMemory { memory }
+    }
+
+    fn expand_to(&mut self, capacity: usize) {
+        let mut extension = vec![0; capacity - self.memory.len()];
+        self.memory.reserve(extension.len());
+        self.memory.append(&mut extension);
+    }
+}
+
+impl MemoryBackend for DenseMemory {
+    fn get(&self, index: usize) -> i64 {
+        self.memory.get(index).copied().unwrap_or(0)
+    }
+
+    fn set(&mut self, index: usize, value: i64) {
+        if self.memory.len() <= index {
+            self.expand_to(index + 1);
+        }
+
+        self.memory[index] = value;
+    }
+
+    fn cells(&self) -> Vec<(usize, i64)> {
+        self.memory.iter().copied().enumerate().collect()
+    }
+
+    fn high_water(&self) -> usize {
+        self.memory.len()
+    }
+
+    fn clone_box(&self) -> Box<dyn MemoryBackend> {
+        Box::new(self.clone())
+    }
+}
+
+impl SparseMemory {
+    pub fn new(image: Vec<i64>) -> Self {
+        SparseMemory {
+            image,
+            low_pages: Vec::new(),
+            pages: HashMap::new(),
+        }
+    }
+
+    fn page(&self, page: usize) -> Option<&[i64]> {
+        if page < LOW_PAGES {
+            self.low_pages.get(page)?.as_deref()
+        } else {
+            self.pages.get(&page).map(|words| &words[..])
+        }
+    }
+
+    fn page_mut(&mut self, page: usize) -> &mut [i64] {
+        let new_page = || vec![0; PAGE_SIZE].into_boxed_slice();
+
+        if page < LOW_PAGES {
+            if self.low_pages.len() <= page {
+                self.low_pages.resize(page + 1, None);
+            }
+
+            self.low_pages[page].get_or_insert_with(new_page)
+        } else {
+            self.pages.entry(page).or_insert_with(new_page)
+        }
+    }
+
+    fn pages(&self) -> Vec<(usize, &[i64])> {
+        let low = self
+            .low_pages
+            .iter()
+            .enumerate()
+            .filter_map(|(page, words)| Some((page, words.as_deref()?)));
+        let mut high: Vec<_> = self
+            .pages
+            .iter()
+            .map(|(&page, words)| (page, &words[..]))
+            .collect();
+        high.sort_unstable_by_key(|&(page, _)| page);
+
+        low.chain(high).collect()
+    }
+}
+
+impl MemoryBackend for SparseMemory {
+    fn get(&self, index: usize) -> i64 {
+        if index < self.image.len() {
+            self.image[index]
+        } else {
+            self.page(index / PAGE_SIZE)
+                .map_or(0, |page| page[index % PAGE_SIZE])
+        }
+    }
+
+    fn set(&mut self, index: usize, value: i64) {
+        if index < self.image.len() {
+            self.image[index] = value;
+            return;
+        }
+
+        let page = index / PAGE_SIZE;
+
+        // writing a zero to a page that doesn't exist yet doesn't change anything
+        if value == 0 && self.page(page).is_none() {
+            return;
+        }
+
+        self.page_mut(page)[index % PAGE_SIZE] = value;
+    }
+
+    fn cells(&self) -> Vec<(usize, i64)> {
+        let image = self.image.iter().copied().enumerate();
+        let paged = self.pages().into_iter().flat_map(|(page, words)| {
+            words
+                .iter()
+                .enumerate()
+                .map(move |(offset, &value)| (page * PAGE_SIZE + offset, value))
+                .filter(|&(_, value)| value != 0)
+        });
+
+        image
+            .chain(paged.filter(|&(index, _)| index >= self.image.len()))
+            .collect()
+    }
+
+    fn high_water(&self) -> usize {
+        let paged = self
+            .pages()
+            .last()
+            .map_or(0, |&(page, _)| (page + 1).saturating_mul(PAGE_SIZE));
+
+        self.image.len().max(paged)
+    }
+
+    fn clone_box(&self) -> Box<dyn MemoryBackend> {
+        Box::new(self.clone())
+    }
+}
+
+impl Backend {
+    #[inline(always)]
+    pub(super) fn get(&self, index: usize) -> i64 {
+        match self {
+            Self::Sparse(memory) => memory.get(index),
+            Self::Boxed(memory) => memory.get(index),
+        }
+    }
+
+    #[inline(always)]
+    pub(super) fn set(&mut self, index: usize, value: i64) {
+        match self {
+            Self::Sparse(memory) => memory.set(index, value),
+            Self::Boxed(memory) => memory.set(index, value),
+        }
+    }
+
+    pub(super) fn cells(&self) -> Vec<(usize, i64)> {
+        self.as_dyn().cells()
+    }
+
+    pub(super) fn high_water(&self) -> usize {
+        self.as_dyn().high_water()
+    }
+
+    fn as_dyn(&self) -> &dyn MemoryBackend {
+        match self {
+            Self::Sparse(memory) => memory,
+            Self::Boxed(memory) => memory.as_ref(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryBackend, SparseMemory};
+
+    #[test]
+    fn writes_anywhere_only_allocate_a_page() {
+        let mut memory = SparseMemory::new(vec![1, 2, 3]);
+        memory.set(usize::MAX, 5);
+        memory.set(i64::MAX as usize, 6);
+
+        assert_eq!(memory.get(usize::MAX), 5);
+        assert_eq!(memory.get(i64::MAX as usize), 6);
+        assert_eq!(memory.get(usize::MAX - 1), 0);
+        assert_eq!(memory.pages().len(), 2);
+        assert!(memory.low_pages.is_empty());
+        assert_eq!(
+            memory.cells(),
+            vec![
+                (0, 1),
+                (1, 2),
+                (2, 3),
+                (i64::MAX as usize, 6),
+                (usize::MAX, 5)
+            ]
+        );
+        assert_eq!(memory.high_water(), usize::MAX);
+    }
+
+    #[test]
+    fn unallocated_pages_read_as_zero() {
+        let memory = SparseMemory::new(vec![1]);
+
+        assert_eq!(memory.get(1), 0);
+        assert_eq!(memory.get(5000), 0);
+        assert_eq!(memory.get(1 << 40), 0);
+        assert!(memory.pages().is_empty());
+        assert_eq!(memory.high_water(), 1);
+    }
+
+    #[test]
+    fn zero_writes_allocate_nothing() {
+        let mut memory = SparseMemory::new(vec![1]);
+        memory.set(5000, 0);
+        memory.set(1 << 40, 0);
+        assert!(memory.pages().is_empty());
+
+        // zeroes still overwrite words that were set before
+        memory.set(0, 0);
+        memory.set(5000, 7);
+        memory.set(5000, 0);
+        assert_eq!(memory.get(0), 0);
+        assert_eq!(memory.get(5000), 0);
+        assert_eq!(memory.pages().len(), 1);
+        assert_eq!(memory.cells(), vec![(0, 0)]);
+    }
+}
@@ -0,0 +1,413 @@
+use super::{IntcodeError, IntcodeVM, RunState};
+use std::{collections::VecDeque, convert::TryFrom, error::Error, fmt};
+
+pub const NAT_ADDRESS: usize = 255;
+// nodes can spend a while polling before they send anything, so a network is only given up on
+// once this many rounds in a row go by without a packet, or the NAT has sent the same packet this
+// many times in a row
+const IDLE_ROUND_LIMIT: usize = 100;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NetworkError {
+    // the node addresses would run into the NAT's
+    TooManyNodes(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub round: usize,
+    pub source: usize,
+    pub destination: i64,
+    pub x: i64,
+    pub y: i64,
+}
+
+#[derive(Debug)]
+struct Node {
+    vm: IntcodeVM,
+    queue: VecDeque<(i64, i64)>,
+    outgoing: Vec<i64>,
+    halted: bool,
+}
+
+// every round each node in address order gets its queued packets, or -1 if there are none, and
+// runs until it waits for more input. packets sent to a node are queued for its next turn, which
+// can be later in the same round. packets to addresses nobody has are logged and dropped
+#[derive(Debug)]
+pub struct Network {
+    nodes: Vec<Node>,
+    nat: Option<Packet>,
+    log: Vec<Packet>,
+    round: usize,
+}
+
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "round {}: {} -> {} ({}, {})",
+            self.round, self.source, self.destination, self.x, self.y
+        )
+    }
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TooManyNodes(size) => write!(
+                f,
+                "a network of {} nodes would use the NAT address {}",
+                size, NAT_ADDRESS
+            ),
+        }
+    }
+}
+
+impl Error for NetworkError {}
+
+impl Node {
+    fn boot(program: &[i64], address: usize) -> Self {
+        let mut vm = IntcodeVM::new(program.to_vec());
+        vm.input(address as i64);
+
+        Node {
+            vm,
+            queue: VecDeque::new(),
+            outgoing: Vec::new(),
+            halted: false,
+        }
+    }
+
+    // returns whether the node had any packets waiting, and the packets it sent
+    fn turn(&mut self) -> Result<(bool, Vec<[i64; 3]>), IntcodeError> {
+        let received = !self.queue.is_empty();
+        let mut sent = Vec::new();
+
+        if received {
+            for (x, y) in self.queue.drain(..) {
+                self.vm.input(x);
+                self.vm.input(y);
+            }
+        } else {
+            self.vm.input(-1);
+        }
+
+        loop {
+            match self.vm.run()? {
+                RunState::Output(value) => {
+                    self.outgoing.push(value);
+
+                    if let [destination, x, y] = self.outgoing[..] {
+                        sent.push([destination, x, y]);
+                        self.outgoing.clear();
+                    }
+                }
+                RunState::NeedsInput => break,
+                RunState::Halted => {
+                    self.halted = true;
+                    break;
+                }
+                RunState::Watchpoint(_) => (),
+            }
+        }
+
+        Ok((received, sent))
+    }
+}
+
+impl Network {
+    // boots a node running the program for every address below the size, each getting its
+    // address as its first input
+    pub fn new(program: &[i64], size: usize) -> Result<Self, NetworkError> {
+        if size > NAT_ADDRESS {
+            return Err(NetworkError::TooManyNodes(size));
+        }
+
+        Ok(Network {
+            nodes: (0..size)
+                .map(|address| Node::boot(program, address))
+                .collect(),
+            nat: None,
+            log: Vec::new(),
+            round: 0,
+        })
+    }
+
+    pub fn node(&self, address: usize) -> Option<&IntcodeVM> {
+        self.nodes.get(address).map(|node| &node.vm)
+    }
+
+    // the last packet the NAT received
+    pub fn nat(&self) -> Option<&Packet> {
+        self.nat.as_ref()
+    }
+
+    // every packet sent so far, including the ones the NAT sent
+    pub fn log(&self) -> &[Packet] {
+        &self.log
+    }
+
+    fn route(&mut self, packet: Packet) {
+        if packet.destination == NAT_ADDRESS as i64 {
+            self.nat = Some(packet);
+        } else if let Some(node) = usize::try_from(packet.destination)
+            .ok()
+            .and_then(|destination| self.nodes.get_mut(destination))
+        {
+            node.queue.push_back((packet.x, packet.y));
+        }
+
+        self.log.push(packet);
+    }
+
+    // runs every node once. if none of them had anything to receive and nothing was sent, the
+    // network is idle and the NAT sends its last packet to address 0. returns the packets sent
+    // during the round
+    pub fn round(&mut self) -> Result<&[Packet], IntcodeError> {
+        let start = self.log.len();
+        let mut idle = true;
+
+        for address in 0..self.nodes.len() {
+            if self.nodes[address].halted {
+                continue;
+            }
+
+            let (received, sent) = self.nodes[address].turn()?;
+            idle &= !received && sent.is_empty();
+
+            for [destination, x, y] in sent {
+                self.route(Packet {
+                    round: self.round,
+                    source: address,
+                    destination,
+                    x,
+                    y,
+                });
+            }
+        }
+
+        if idle {
+            if let Some(packet) = self.nat {
+                self.route(Packet {
+                    round: self.round,
+                    source: NAT_ADDRESS,
+                    destination: 0,
+                    ..packet
+                });
+            }
+        }
+
+        self.round += 1;
+        Ok(&self.log[start..])
+    }
+
+    fn is_dead(&self) -> bool {
+        self.nodes.iter().all(|node| node.halted)
+    }
+
+    // runs rounds until a packet matching the predicate is sent and returns it. returns None if
+    // every node halts, enough rounds go by without packets while the NAT has nothing to send, or
+    // the NAT keeps waking the network with the same packet
+    pub fn run_until<F>(&mut self, mut predicate: F) -> Result<Option<Packet>, IntcodeError>
+    where
+        F: FnMut(&Packet) -> bool,
+    {
+        let mut idle_rounds = 0;
+        // the X and Y the NAT last sent, and how many times in a row it's sent them
+        let mut resent = None;
+        let mut resends = 0;
+
+        while !self.is_dead() {
+            let packets = self.round()?;
+
+            if let Some(packet) = packets.iter().find(|packet| predicate(packet)) {
+                return Ok(Some(*packet));
+            }
+
+            if let Some(packet) = packets.iter().find(|packet| packet.source == NAT_ADDRESS) {
+                let values = Some((packet.x, packet.y));
+                resends = if values == resent { resends + 1 } else { 1 };
+                resent = values;
+
+                if resends == IDLE_ROUND_LIMIT {
+                    return Ok(None);
+                }
+            }
+
+            if packets.is_empty() && self.nat.is_none() {
+                idle_rounds += 1;
+
+                if idle_rounds == IDLE_ROUND_LIMIT {
+                    return Ok(None);
+                }
+            } else {
+                idle_rounds = 0;
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::asm::assemble, Network, NetworkError, IDLE_ROUND_LIMIT, NAT_ADDRESS};
+
+    #[test]
+    fn network_routes_packets_through_nat() {
+        // node 0 kicks things off, after that every node bumps the Y of whatever it receives and
+        // sends it to the NAT
+        let program = assemble(
+            "
+            INPUT [address]
+            JUMP_IF_TRUE [address], #loop
+            OUTPUT #1
+            OUTPUT #7
+            OUTPUT #10
+        loop:
+            INPUT [x]
+            EQUALS [x], #-1, [idle]
+            JUMP_IF_TRUE [idle], #loop
+            INPUT [y]
+            ADD [y], #1, [y]
+            OUTPUT #255
+            OUTPUT [x]
+            OUTPUT [y]
+            JUMP_IF_TRUE #1, #loop
+        address:
+            .data 0
+        x:
+            .data 0
+        y:
+            .data 0
+        idle:
+            .data 0
+        ",
+        )
+        .unwrap();
+
+        let mut network = Network::new(&program, 3).unwrap();
+        let packet = network
+            .run_until(|packet| packet.source == NAT_ADDRESS && packet.y == 13)
+            .unwrap()
+            .unwrap();
+        assert_eq!((packet.destination, packet.x), (0, 7));
+
+        let log: Vec<_> = network
+            .log()
+            .iter()
+            .map(|packet| (packet.source, packet.destination, packet.y))
+            .collect();
+        assert_eq!(
+            log,
+            vec![
+                (0, 1, 10),
+                (1, 255, 11),
+                (255, 0, 11),
+                (0, 255, 12),
+                (255, 0, 12),
+                (0, 255, 13),
+                (255, 0, 13),
+            ]
+        );
+    }
+
+    #[test]
+    fn network_waits_for_nodes_that_start_slowly() {
+        // polls a few times before sending anything, then goes quiet for good
+        let program = assemble(
+            "
+            INPUT [address]
+        poll:
+            INPUT [x]
+            ADD [polls], #-1, [polls]
+            JUMP_IF_TRUE [polls], #poll
+            OUTPUT #255
+            OUTPUT #1
+            OUTPUT #2
+        quiet:
+            INPUT [x]
+            JUMP_IF_TRUE #1, #quiet
+        address:
+            .data 0
+        x:
+            .data 0
+        polls:
+            .data 5
+        ",
+        )
+        .unwrap();
+
+        let mut network = Network::new(&program, 1).unwrap();
+        let packet = network
+            .run_until(|packet| packet.destination == NAT_ADDRESS as i64)
+            .unwrap()
+            .unwrap();
+        assert_eq!((packet.round, packet.x, packet.y), (4, 1, 2));
+
+        // with nothing ever sent the network is given up on instead of running forever
+        let silent = assemble(
+            "
+        quiet:
+            INPUT [x]
+            JUMP_IF_TRUE #1, #quiet
+        x:
+            .data 0
+        ",
+        )
+        .unwrap();
+        let mut silent = Network::new(&silent, 2).unwrap();
+        assert_eq!(silent.run_until(|_| true).unwrap(), None);
+        assert_eq!(silent.round, IDLE_ROUND_LIMIT);
+    }
+
+    #[test]
+    fn network_stops_when_the_nat_keeps_sending_the_same_packet() {
+        // sends a packet to the NAT, then sends back whatever the NAT wakes it with
+        let program = assemble(
+            "
+            INPUT [x]
+            OUTPUT #255
+            OUTPUT #1
+            OUTPUT #2
+        loop:
+            INPUT [x]
+            EQUALS [x], #-1, [idle]
+            JUMP_IF_TRUE [idle], #loop
+            INPUT [y]
+            OUTPUT #255
+            OUTPUT [x]
+            OUTPUT [y]
+            JUMP_IF_TRUE #1, #loop
+        x:
+            .data 0
+        y:
+            .data 0
+        idle:
+            .data 0
+        ",
+        )
+        .unwrap();
+
+        let mut network = Network::new(&program, 1).unwrap();
+        assert_eq!(network.run_until(|_| false).unwrap(), None);
+
+        let resends = network
+            .log()
+            .iter()
+            .filter(|packet| packet.source == NAT_ADDRESS)
+            .count();
+        assert_eq!(resends, IDLE_ROUND_LIMIT);
+    }
+
+    #[test]
+    fn network_size_cant_reach_nat_address() {
+        let program = assemble("HALT").unwrap();
+
+        assert!(Network::new(&program, NAT_ADDRESS).is_ok());
+        assert_eq!(
+            Network::new(&program, NAT_ADDRESS + 1).unwrap_err(),
+            NetworkError::TooManyNodes(NAT_ADDRESS + 1)
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use super::{IntcodeError, IntcodeVM};
+use std::{cell::Cell, error::Error, fmt, ops::RangeInclusive};
+
+// noun and verb programs take their inputs from these addresses and leave the result in the first
+const RESULT_ADDRESS: usize = 0;
+const NOUN_ADDRESS: usize = 1;
+const VERB_ADDRESS: usize = 2;
+// a patched program running longer than this is taken to be stuck
+const STEP_BUDGET: u64 = 1_000_000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NounVerbError {
+    // no noun and verb in the range make the program leave the target behind
+    NoSolution { target: i64 },
+    // nothing matched, and this is the first run that failed along the way
+    Fault(IntcodeError),
+}
+
+impl fmt::Display for NounVerbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoSolution { target } => write!(f, "no noun and verb give {}", target),
+            Self::Fault(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for NounVerbError {}
+
+impl From<IntcodeError> for NounVerbError {
+    fn from(error: IntcodeError) -> Self {
+        Self::Fault(error)
+    }
+}
+
+// runs the program with the noun and verb patched in and returns what it leaves at address 0
+pub fn run_noun_verb(program: &[i64], noun: i64, verb: i64) -> Result<i64, IntcodeError> {
+    let mut vm = IntcodeVM::new(program.to_vec());
+    vm.memory.store(NOUN_ADDRESS, noun);
+    vm.memory.store(VERB_ADDRESS, verb);
+    vm.set_step_budget(Some(STEP_BUDGET));
+
+    while vm.get_next_output()?.is_some() {}
+
+    Ok(vm.memory.fetch(RESULT_ADDRESS))
+}
+
+// the pairs a result linear in the noun and verb would hit the target with, worked out from three
+// runs at the start of the range. the guesses still have to be checked by running them
+fn linear_guesses<F>(
+    run: F,
+    target: i64,
+    range: RangeInclusive<i64>,
+) -> Option<impl Iterator<Item = (i64, i64)>>
+where
+    F: Fn(i64, i64) -> Option<i64>,
+{
+    let start = *range.start();
+    let next = start.checked_add(1)?;
+    let base = run(start, start)?;
+    let per_noun = run(next, start)?.checked_sub(base)?;
+    let per_verb = run(start, next)?.checked_sub(base)?;
+
+    Some(range.clone().filter_map(move |noun| {
+        let rest = target
+            .checked_sub(base)?
+            .checked_sub(per_noun.checked_mul(noun.checked_sub(start)?)?)?;
+        let verb = match per_verb {
+            0 if rest == 0 => start,
+            0 => return None,
+            // dividing i64::MIN by -1 overflows, and no verb in an i64 range is that far out anyway
+            _ if rest.checked_rem(per_verb)? != 0 => return None,
+            _ => start.checked_add(rest.checked_div(per_verb)?)?,
+        };
+
+        range.contains(&verb).then_some((noun, verb))
+    }))
+}
+
+// finds a noun and verb from the range that make the program leave the target at address 0. the
+// result is usually linear in both so a handful of runs are enough, and if that doesn't pan out
+// every pair is tried in order. pairs the program fails with are skipped, but if nothing matches
+// the first failure is returned rather than NoSolution
+pub fn solve_noun_verb(
+    program: &[i64],
+    target: i64,
+    range: RangeInclusive<i64>,
+) -> Result<(i64, i64), NounVerbError> {
+    let first_fault = Cell::new(None);
+    let run = |noun, verb| {
+        run_noun_verb(program, noun, verb)
+            .map_err(|error| first_fault.set(first_fault.get().or(Some(error))))
+            .ok()
+    };
+    let hits = |&(noun, verb): &(i64, i64)| run(noun, verb) == Some(target);
+
+    linear_guesses(run, target, range.clone())
+        .and_then(|mut guesses| guesses.find(hits))
+        .or_else(|| {
+            range
+                .clone()
+                .flat_map(|noun| range.clone().map(move |verb| (noun, verb)))
+                .find(hits)
+        })
+        .ok_or_else(|| {
+            first_fault
+                .get()
+                .map_or(NounVerbError::NoSolution { target }, NounVerbError::Fault)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{asm::assemble, IntcodeError},
+        run_noun_verb, solve_noun_verb, NounVerbError,
+    };
+
+    #[test]
+    fn noun_verb_solver_handles_linear_and_other_programs() {
+        // leaves 100 * noun + verb at address 0, like day 2
+        let linear = assemble(
+            "
+            ADD #0, #0, [sum]
+            MUL [1], #99, [scaled]
+            ADD [sum], [scaled], [0]
+            HALT
+        sum:
+            .data 0
+        scaled:
+            .data 0
+        ",
+        )
+        .unwrap();
+        let product = assemble("MUL #0, #0, [0]\nHALT").unwrap();
+
+        assert_eq!(run_noun_verb(&linear, 12, 34), Ok(1234));
+        assert_eq!(solve_noun_verb(&linear, 1234, 0..=99), Ok((12, 34)));
+        assert_eq!(solve_noun_verb(&product, 391, 0..=99), Ok((17, 23)));
+        assert_eq!(
+            solve_noun_verb(&product, 9973, 0..=99),
+            Err(NounVerbError::NoSolution { target: 9973 })
+        );
+    }
+
+    #[test]
+    fn noun_verb_search_survives_overflowing_guesses() {
+        // address 0 ends up as -verb, so the verb needed for i64::MIN doesn't fit
+        let negated = [1101, 0, 0, 9, 1002, 2, -1, 0, 99, 0];
+
+        assert_eq!(run_noun_verb(&negated, 0, 5), Ok(-5));
+        assert_eq!(
+            solve_noun_verb(&negated, i64::MIN, 0..=99),
+            Err(NounVerbError::NoSolution { target: i64::MIN })
+        );
+
+        // there's no second noun or verb to work out the slopes from
+        assert_eq!(
+            solve_noun_verb(&negated, -i64::MAX, i64::MAX..=i64::MAX),
+            Ok((i64::MAX, i64::MAX))
+        );
+    }
+
+    #[test]
+    fn noun_verb_search_reports_failing_programs() {
+        // writes to its own immediate parameter whatever the noun and verb are
+        let broken = [11101, 0, 0, 0, 99];
+
+        assert_eq!(
+            solve_noun_verb(&broken, 0, 0..=9),
+            Err(NounVerbError::Fault(IntcodeError::WriteToImmediate {
+                ip: 0,
+                instruction: 11101
+            }))
+        );
+    }
+}
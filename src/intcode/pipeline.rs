@@ -0,0 +1,163 @@
+use super::{IntcodeError, IntcodeVM, RunState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    // the last stage's outputs leave the pipeline
+    Chain,
+    // the last stage's outputs go back into the first stage
+    Ring,
+}
+
+#[derive(Debug)]
+pub struct Pipeline {
+    stages: Vec<IntcodeVM>,
+    topology: Topology,
+}
+
+impl Pipeline {
+    pub fn new(stages: Vec<IntcodeVM>, topology: Topology) -> Self {
+        Pipeline { stages, topology }
+    }
+
+    // one copy of the program per phase, each stage getting its phase as its first input
+    pub fn with_phases(program: &[i64], phases: &[i64], topology: Topology) -> Self {
+        let stages = phases
+            .iter()
+            .map(|&phase| {
+                let mut vm = IntcodeVM::new(program.to_vec());
+                vm.input(phase);
+                vm
+            })
+            .collect();
+
+        Self::new(stages, topology)
+    }
+
+    pub fn stages(&self) -> &[IntcodeVM] {
+        &self.stages
+    }
+
+    pub fn into_stages(self) -> Vec<IntcodeVM> {
+        self.stages
+    }
+
+    // feeds the signal to the first stage and runs every stage until they've all halted. returns
+    // the last value the last stage output, or None if it never output anything. if the stages
+    // still running are all waiting for input nobody is going to give them, the first of them
+    // reports an input underflow
+    pub fn run(&mut self, signal: i64) -> Result<Option<i64>, IntcodeError> {
+        let count = self.stages.len();
+        let mut halted = vec![false; count];
+        let mut last_signal = None;
+
+        if let Some(first) = self.stages.first_mut() {
+            first.input(signal);
+        }
+
+        while halted.contains(&false) {
+            let mut progressed = false;
+
+            for (stage, stage_halted) in halted.iter_mut().enumerate() {
+                if *stage_halted {
+                    continue;
+                }
+
+                loop {
+                    match self.stages[stage].run()? {
+                        RunState::Output(value) => {
+                            progressed = true;
+
+                            if stage + 1 < count {
+                                self.stages[stage + 1].input(value);
+                            } else {
+                                last_signal = Some(value);
+
+                                if self.topology == Topology::Ring {
+                                    self.stages[0].input(value);
+                                }
+                            }
+                        }
+                        RunState::Halted => {
+                            progressed = true;
+                            *stage_halted = true;
+                            break;
+                        }
+                        RunState::NeedsInput => break,
+                        RunState::Watchpoint(_) => (),
+                    }
+                }
+            }
+
+            if !progressed {
+                let stalled = halted.iter().position(|&halted| !halted).unwrap();
+                return Err(self.stages[stalled].input_underflow());
+            }
+        }
+
+        Ok(last_signal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::asm::assemble, IntcodeError, Pipeline, Topology};
+
+    // adds the phase to every signal that comes in, rounds times, then halts
+    fn adder(rounds: i64) -> Vec<i64> {
+        assemble(&format!(
+            "
+            INPUT [phase]
+        loop:
+            INPUT [signal]
+            ADD [signal], [phase], [signal]
+            OUTPUT [signal]
+            ADD [rounds], #-1, [rounds]
+            JUMP_IF_TRUE [rounds], #loop
+            HALT
+        phase:
+            .data 0
+        signal:
+            .data 0
+        rounds:
+            .data {}
+        ",
+            rounds
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn chain_passes_the_signal_through_every_stage() {
+        let mut pipeline = Pipeline::with_phases(&adder(1), &[1, 10, 100], Topology::Chain);
+
+        assert_eq!(pipeline.run(5), Ok(Some(116)));
+        assert_eq!(pipeline.stages().len(), 3);
+    }
+
+    #[test]
+    fn ring_feeds_the_last_stage_back_into_the_first() {
+        let mut pipeline = Pipeline::with_phases(&adder(3), &[1, 2], Topology::Ring);
+
+        // three trips around the ring, each adding 1 + 2
+        assert_eq!(pipeline.run(0), Ok(Some(9)));
+    }
+
+    #[test]
+    fn stalled_stages_are_an_input_underflow() {
+        // a chain doesn't feed the first stage again, so its second round never gets a signal
+        let mut pipeline = Pipeline::with_phases(&adder(2), &[1, 2], Topology::Chain);
+
+        assert_eq!(
+            pipeline.run(0),
+            Err(IntcodeError::InputUnderflow {
+                ip: 2,
+                instruction: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn empty_pipeline_outputs_nothing() {
+        assert_eq!(Pipeline::new(Vec::new(), Topology::Ring).run(0), Ok(None));
+    }
+}
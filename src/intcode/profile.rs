@@ -0,0 +1,176 @@
+use super::{IntcodeVM, Opcode};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    ops::Range,
+};
+
+// how many of the hottest ranges the report lists
+const REPORT_RANGES: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Counter {
+    executions: u64,
+    len: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    steps: u64,
+    addresses: HashMap<usize, Counter>,
+    opcodes: BTreeMap<&'static str, u64>,
+    memory_high_water: usize,
+}
+
+// a run of instructions right after each other that were all executed the same number of times,
+// which is usually a basic block or a whole loop body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotRange {
+    pub range: Range<usize>,
+    pub instructions: usize,
+    pub executions: u64,
+}
+
+impl HotRange {
+    pub fn steps(&self) -> u64 {
+        self.instructions as u64 * self.executions
+    }
+}
+
+impl Profile {
+    pub(super) fn record(&mut self, ip: usize, opcode: &Opcode, mnemonic: &'static str) {
+        self.steps += 1;
+        *self.opcodes.entry(mnemonic).or_insert(0) += 1;
+
+        let counter = self.addresses.entry(ip).or_insert(Counter {
+            executions: 0,
+            len: opcode.len(),
+        });
+        counter.executions += 1;
+        counter.len = opcode.len();
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn executions_at(&self, address: usize) -> u64 {
+        self.addresses
+            .get(&address)
+            .map_or(0, |counter| counter.executions)
+    }
+
+    // the most executed opcodes first
+    pub fn opcodes(&self) -> Vec<(&'static str, u64)> {
+        let mut opcodes: Vec<_> = self
+            .opcodes
+            .iter()
+            .map(|(&mnemonic, &count)| (mnemonic, count))
+            .collect();
+        opcodes.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        opcodes
+    }
+
+    // one past the highest address the memory backend had room for when profiling stopped
+    pub fn memory_high_water(&self) -> usize {
+        self.memory_high_water
+    }
+
+    // the ranges that took the most steps, at most count of them
+    pub fn hottest(&self, count: usize) -> Vec<HotRange> {
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort_unstable_by_key(|&(&address, _)| address);
+
+        let mut ranges: Vec<HotRange> = Vec::new();
+
+        for (&address, counter) in addresses {
+            match ranges.last_mut() {
+                Some(last)
+                    if last.range.end == address && last.executions == counter.executions =>
+                {
+                    last.range.end = address + counter.len;
+                    last.instructions += 1;
+                }
+                _ => ranges.push(HotRange {
+                    range: address..address + counter.len,
+                    instructions: 1,
+                    executions: counter.executions,
+                }),
+            }
+        }
+
+        ranges.sort_by_key(|range| (std::cmp::Reverse(range.steps()), range.range.start));
+        ranges.truncate(count);
+        ranges
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let share = |steps: u64| steps as f64 / self.steps.max(1) as f64 * 100.0;
+
+        writeln!(f, "steps: {}", self.steps)?;
+        writeln!(f, "memory high-water mark: {}", self.memory_high_water)?;
+
+        writeln!(f, "opcodes:")?;
+        for (mnemonic, count) in self.opcodes() {
+            writeln!(f, "  {:<20} {:>10} {:>6.2}%", mnemonic, count, share(count))?;
+        }
+
+        writeln!(f, "hottest ranges:")?;
+        for range in self.hottest(REPORT_RANGES) {
+            writeln!(
+                f,
+                "  {:04}-{:04} {:>3} x {:>10} {:>6.2}%",
+                range.range.start,
+                range.range.end - 1,
+                range.instructions,
+                range.executions,
+                share(range.steps())
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl IntcodeVM {
+    pub fn start_profile(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        let mut profile = self.profile.take()?;
+        profile.memory_high_water = self.memory.backend.high_water();
+        Some(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{tests::vm, RunState};
+
+    #[test]
+    fn profile_counts_hot_loop() {
+        let mut vm = vm("
+        loop:
+            ADD [counter], #-1, [counter]
+            JUMP_IF_TRUE [counter], #loop
+            ADD #1, #0, [3000]
+            HALT
+        counter:
+            .data 5
+        ");
+
+        vm.start_profile();
+        assert_eq!(vm.run(), Ok(RunState::Halted));
+        let profile = vm.take_profile().unwrap();
+
+        assert_eq!(profile.steps(), 12);
+        assert_eq!(profile.executions_at(4), 5);
+        assert_eq!(profile.opcodes()[0], ("ADD", 6));
+        assert_eq!(profile.hottest(1)[0].range, 0..7);
+        assert_eq!(profile.hottest(1)[0].steps(), 10);
+        assert!(profile.memory_high_water() > 3000);
+    }
+}
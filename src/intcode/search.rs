@@ -0,0 +1,235 @@
+use super::{
+    pipeline::{Pipeline, Topology},
+    IntcodeError,
+};
+use std::{
+    error::Error,
+    fmt,
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+};
+
+// permutations handed out to a worker at a time
+const BATCH_SIZE: usize = 64;
+// searches this small are over before threads would be done starting, so they run on the caller's
+// thread
+const SERIAL_LIMIT: usize = 720;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub phases: Vec<i64>,
+    pub signal: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchError {
+    // the permutations don't even fit in a count
+    TooManyPhases { phases: usize },
+    Fault(IntcodeError),
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TooManyPhases { phases } => {
+                write!(f, "{} phases have too many permutations to search", phases)
+            }
+            Self::Fault(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for SearchError {}
+
+impl From<IntcodeError> for SearchError {
+    fn from(error: IntcodeError) -> Self {
+        Self::Fault(error)
+    }
+}
+
+// the index'th permutation of the phases in lexicographic order of their positions
+fn nth_permutation(phases: &[i64], mut index: usize) -> Vec<i64> {
+    let mut remaining = phases.to_vec();
+    let mut permutation = Vec::with_capacity(phases.len());
+
+    for left in (1..=phases.len()).rev() {
+        let block = (1..left).product::<usize>();
+        permutation.push(remaining.remove(index / block));
+        index %= block;
+    }
+
+    permutation
+}
+
+// better signals win, and on equal signals the earlier permutation does so the result doesn't
+// depend on how the work got split up
+fn better(candidate: (usize, i64), best: Option<(usize, i64)>) -> bool {
+    match best {
+        Some((index, signal)) => {
+            candidate.1 > signal || (candidate.1 == signal && candidate.0 < index)
+        }
+        None => true,
+    }
+}
+
+// runs the pipeline for the permutations with the given indices, keeping the best one in best
+fn search_permutations(
+    program: &[i64],
+    phases: &[i64],
+    topology: Topology,
+    indices: Range<usize>,
+    best: &mut Option<(usize, i64)>,
+) -> Result<(), IntcodeError> {
+    for index in indices {
+        let permutation = nth_permutation(phases, index);
+        let mut pipeline = Pipeline::with_phases(program, &permutation, topology);
+
+        if let Some(signal) = pipeline.run(0)? {
+            if better((index, signal), *best) {
+                *best = Some((index, signal));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// runs a pipeline for every ordering of the phases, on all available cores when there are enough
+// of them, and returns the one giving the highest signal out of the last stage. permutations where
+// the last stage doesn't output anything are skipped, and if none of them do, the result is None.
+// with more phases than their permutations can be counted for, that's an error
+pub fn best_phases(
+    program: &[i64],
+    phases: &[i64],
+    topology: Topology,
+) -> Result<Option<SearchResult>, SearchError> {
+    let total = (1..=phases.len())
+        .try_fold(1usize, |total, n| total.checked_mul(n))
+        .ok_or(SearchError::TooManyPhases {
+            phases: phases.len(),
+        })?;
+
+    let results = if total <= SERIAL_LIMIT {
+        let mut best = None;
+        search_permutations(program, phases, topology, 0..total, &mut best)?;
+        vec![Ok(best)]
+    } else {
+        search_in_parallel(program, phases, topology, total)
+    };
+
+    let mut best = None;
+
+    for result in results {
+        if let Some(candidate) = result? {
+            if better(candidate, best) {
+                best = Some(candidate);
+            }
+        }
+    }
+
+    Ok(best.map(|(index, signal)| SearchResult {
+        phases: nth_permutation(phases, index),
+        signal,
+    }))
+}
+
+// workers take batches of permutations until they run out or one of them fails, each one's best
+// is returned for the caller to pick from
+fn search_in_parallel(
+    program: &[i64],
+    phases: &[i64],
+    topology: Topology,
+    total: usize,
+) -> Vec<Result<Option<(usize, i64)>, IntcodeError>> {
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut best = None;
+
+                    while !failed.load(Ordering::Relaxed) {
+                        let start = next.fetch_add(BATCH_SIZE, Ordering::Relaxed);
+                        if start >= total {
+                            break;
+                        }
+
+                        let batch = start..total.min(start + BATCH_SIZE);
+                        if let Err(e) =
+                            search_permutations(program, phases, topology, batch, &mut best)
+                        {
+                            failed.store(true, Ordering::Relaxed);
+                            return Err(e);
+                        }
+                    }
+
+                    Ok(best)
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{asm::assemble, pipeline::Topology},
+        best_phases, SearchError,
+    };
+
+    #[test]
+    fn phase_search_finds_best_permutation() {
+        // each stage appends its phase to the signal as a decimal digit
+        let program = assemble(
+            "
+            INPUT [phase]
+            INPUT [signal]
+            MUL [signal], #10, [signal]
+            ADD [signal], [phase], [signal]
+            OUTPUT [signal]
+            HALT
+        phase:
+            .data 0
+        signal:
+            .data 0
+        ",
+        )
+        .unwrap();
+
+        let best = best_phases(&program, &[2, 4, 1, 3], Topology::Chain)
+            .unwrap()
+            .unwrap();
+        assert_eq!(best.phases, vec![4, 3, 2, 1]);
+        assert_eq!(best.signal, 4321);
+
+        // enough permutations to be split up between threads
+        let best = best_phases(&program, &[2, 4, 1, 3, 6, 5, 7], Topology::Chain)
+            .unwrap()
+            .unwrap();
+        assert_eq!(best.phases, vec![7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(best.signal, 7_654_321);
+    }
+
+    #[test]
+    fn too_many_phases_are_an_error() {
+        let phases: Vec<i64> = (0..21).collect();
+
+        assert_eq!(
+            best_phases(&[99], &phases, Topology::Chain),
+            Err(SearchError::TooManyPhases { phases: 21 })
+        );
+        assert_eq!(
+            SearchError::TooManyPhases { phases: 21 }.to_string(),
+            "21 phases have too many permutations to search"
+        );
+    }
+}
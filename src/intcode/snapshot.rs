@@ -0,0 +1,227 @@
+use super::{
+    memory::{Backend, MemoryBackend, SparseMemory},
+    IntcodeVM,
+};
+use std::{collections::VecDeque, error::Error, fmt, fs, io, path::Path, str::FromStr};
+
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    memory: Backend,
+    ip: usize,
+    rel_base_offset: i64,
+    input: VecDeque<i64>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ParseSnapshotError {
+    pub line: usize,
+}
+
+impl Snapshot {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn nonzero_cells(&self) -> Vec<(usize, i64)> {
+        let mut cells = self.memory.cells();
+        cells.retain(|&(_, value)| value != 0);
+        cells
+    }
+}
+
+// two snapshots are equal if they'd behave the same, no matter which backend they came from
+impl PartialEq for Snapshot {
+    fn eq(&self, other: &Self) -> bool {
+        self.ip == other.ip
+            && self.rel_base_offset == other.rel_base_offset
+            && self.input == other.input
+            && self.nonzero_cells() == other.nonzero_cells()
+    }
+}
+
+impl Eq for Snapshot {}
+
+fn join(values: impl Iterator<Item = i64>) -> String {
+    values
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn split_values<T: FromStr>(values: &str) -> Result<Vec<T>, T::Err> {
+    if values.is_empty() {
+        Ok(Vec::new())
+    } else {
+        values.split(',').map(str::parse).collect()
+    }
+}
+
+// memory is written as runs of consecutive words, "start:word,word,..."
+fn memory_runs(cells: Vec<(usize, i64)>) -> String {
+    let mut runs: Vec<(usize, Vec<i64>)> = Vec::new();
+
+    for (address, value) in cells {
+        match runs.last_mut() {
+            Some((start, words)) if *start + words.len() == address => words.push(value),
+            _ => runs.push((address, vec![value])),
+        }
+    }
+
+    runs.into_iter()
+        .map(|(start, words)| format!("{}:{}", start, join(words.into_iter())))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn parse_memory(runs: &str) -> Option<SparseMemory> {
+    let mut memory = SparseMemory::default();
+
+    for (index, run) in runs.split_whitespace().enumerate() {
+        let mut split = run.splitn(2, ':');
+        let start: usize = split.next()?.parse().ok()?;
+        let words: Vec<i64> = split_values(split.next()?).ok()?;
+
+        if index == 0 && start == 0 {
+            memory = SparseMemory::new(words);
+        } else {
+            for (offset, value) in words.into_iter().enumerate() {
+                memory.set(start.checked_add(offset)?, value);
+            }
+        }
+    }
+
+    Some(memory)
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "ip {}", self.ip)?;
+        writeln!(f, "rel_base {}", self.rel_base_offset)?;
+        writeln!(f, "input {}", join(self.input.iter().copied()))?;
+        writeln!(f, "memory {}", memory_runs(self.memory.cells()))
+    }
+}
+
+impl FromStr for Snapshot {
+    type Err = ParseSnapshotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lines: Vec<&str> = s.lines().collect();
+        let field = |index: usize, name: &str| {
+            lines
+                .get(index)
+                .and_then(|line| line.strip_prefix(name))
+                .map(str::trim)
+                .ok_or(ParseSnapshotError { line: index + 1 })
+        };
+        let error = |index: usize| move |_| ParseSnapshotError { line: index + 1 };
+
+        Ok(Snapshot {
+            ip: field(0, "ip")?.parse().map_err(error(0))?,
+            rel_base_offset: field(1, "rel_base")?.parse().map_err(error(1))?,
+            input: split_values(field(2, "input")?).map_err(error(2))?.into(),
+            memory: Backend::Sparse(
+                parse_memory(field(3, "memory")?).ok_or(ParseSnapshotError { line: 4 })?,
+            ),
+        })
+    }
+}
+
+impl fmt::Display for ParseSnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid snapshot field on line {}", self.line)
+    }
+}
+
+impl Error for ParseSnapshotError {}
+
+impl IntcodeVM {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.memory.backend.clone(),
+            ip: self.ip,
+            rel_base_offset: self.rel_base_offset,
+            input: self.input.clone(),
+        }
+    }
+
+    // watchpoints and tracing stay as they are, only the machine state is replaced
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.memory.replace_backend(snapshot.memory.clone());
+        self.ip = snapshot.ip;
+        self.rel_base_offset = snapshot.rel_base_offset;
+        self.input.clone_from(&snapshot.input);
+        self.watch_hits.clear();
+        self.device_error = None;
+    }
+
+    pub fn from_snapshot(snapshot: &Snapshot) -> Self {
+        let mut vm = IntcodeVM::new(Vec::new());
+        vm.restore(snapshot);
+        vm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            tests::{outputs, vm},
+            IntcodeVM, RunState,
+        },
+        ParseSnapshotError, Snapshot,
+    };
+
+    #[test]
+    fn snapshots_resume_a_paused_vm() {
+        let mut vm = vm("
+            RELATIVE_BASE_OFFSET #1000000000
+            INPUT rel[5]
+            OUTPUT rel[5]
+            INPUT [value]
+            ADD rel[5], [value], [value]
+            OUTPUT [value]
+            HALT
+        value:
+            .data 0
+        ");
+        vm.input(7);
+        vm.input(8);
+        assert_eq!(vm.run(), Ok(RunState::Output(7)));
+
+        let snapshot = vm.snapshot();
+        let text = snapshot.to_string();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[..3], ["ip 6", "rel_base 1000000000", "input 8"]);
+        assert!(lines[3].ends_with(" 1000000005:7"));
+
+        let path = std::env::temp_dir().join(format!("intcode-{}.snapshot", std::process::id()));
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, snapshot);
+        assert_eq!(text.parse::<Snapshot>(), Ok(snapshot));
+
+        // both carry on with the input that was still queued
+        let mut restored = IntcodeVM::from_snapshot(&loaded);
+        assert_eq!(outputs(&mut restored), Ok(vec![15]));
+        assert_eq!(outputs(&mut vm), Ok(vec![15]));
+        assert_eq!(restored.snapshot(), vm.snapshot());
+    }
+
+    #[test]
+    fn snapshots_past_the_end_of_memory_are_an_error() {
+        let text = format!("ip 0\nrel_base 0\ninput \nmemory 0:1 {}:1,2\n", usize::MAX);
+
+        assert_eq!(
+            text.parse::<Snapshot>(),
+            Err(ParseSnapshotError { line: 4 })
+        );
+    }
+}
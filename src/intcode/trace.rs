@@ -0,0 +1,382 @@
+use super::{IntcodeError, IntcodeVM, Opcode, RunState};
+use std::{collections::VecDeque, error::Error, fmt, str::FromStr};
+
+// traces keep this many of the most recent instructions unless told otherwise, so tracing a long
+// run doesn't use up all the memory
+pub const DEFAULT_CAPACITY: usize = 1 << 20;
+
+// memory accesses made by the instruction currently being executed
+#[derive(Debug, Default)]
+pub(super) struct Journal {
+    pub(super) reads: Vec<(usize, i64)>,
+    pub(super) writes: Vec<(usize, i64)>,
+}
+
+// immediate operands are in the instruction words, everything read from memory is in reads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub ip: usize,
+    pub words: Vec<i64>,
+    pub reads: Vec<(usize, i64)>,
+    pub writes: Vec<(usize, i64)>,
+    pub output: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    pub entries: VecDeque<TraceEntry>,
+    // how many of the oldest entries were thrown away to make room for newer ones
+    pub dropped: usize,
+    capacity: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ParseTraceError {
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    Diverged {
+        step: usize,
+        expected: Box<TraceEntry>,
+        found: Box<TraceEntry>,
+    },
+    Starved {
+        step: usize,
+    },
+    Fault {
+        step: usize,
+        error: IntcodeError,
+    },
+    // the start of the run is missing, so there's nothing to replay it from
+    Truncated {
+        dropped: usize,
+    },
+}
+
+impl TraceEntry {
+    fn opcode(&self) -> Option<Opcode> {
+        Opcode::decode(0, |index| self.words.get(index).copied().unwrap_or(0)).ok()
+    }
+
+    pub fn instruction(&self) -> String {
+        match self.opcode() {
+            Some(opcode) => opcode.to_string(),
+            None => String::from("DATA"),
+        }
+    }
+
+    // the value an input instruction stored, used to feed the VM while replaying
+    fn input(&self) -> Option<i64> {
+        match (self.opcode(), self.writes.as_slice()) {
+            (Some(Opcode::Input(_)), [(_, value)]) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let words = self
+            .words
+            .iter()
+            .map(|word| word.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        write!(f, "{} {}", self.ip, words)?;
+
+        for (address, value) in &self.reads {
+            write!(f, " r{}={}", address, value)?;
+        }
+
+        for (address, value) in &self.writes {
+            write!(f, " w{}={}", address, value)?;
+        }
+
+        if let Some(value) = self.output {
+            write!(f, " o{}", value)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_access(token: &str) -> Option<(usize, i64)> {
+    let mut split = token.splitn(2, '=');
+    let address = split.next()?.parse().ok()?;
+    let value = split.next()?.parse().ok()?;
+    Some((address, value))
+}
+
+impl FromStr for TraceEntry {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = s.split_whitespace();
+        let ip = tokens.next().ok_or(())?.parse().map_err(|_| ())?;
+        let words = tokens
+            .next()
+            .ok_or(())?
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|_| ())?;
+
+        let mut entry = TraceEntry {
+            ip,
+            words,
+            reads: Vec::new(),
+            writes: Vec::new(),
+            output: None,
+        };
+
+        for token in tokens {
+            if let Some(access) = token.strip_prefix('r') {
+                entry.reads.push(parse_access(access).ok_or(())?);
+            } else if let Some(access) = token.strip_prefix('w') {
+                entry.writes.push(parse_access(access).ok_or(())?);
+            } else if let Some(value) = token.strip_prefix('o') {
+                entry.output = Some(value.parse().map_err(|_| ())?);
+            } else {
+                return Err(());
+            }
+        }
+
+        Ok(entry)
+    }
+}
+
+impl Trace {
+    pub(super) fn with_capacity(capacity: usize) -> Self {
+        Trace {
+            entries: VecDeque::new(),
+            dropped: 0,
+            capacity,
+        }
+    }
+
+    pub(super) fn record(&mut self, entry: TraceEntry) {
+        if self.capacity == 0 {
+            self.dropped += 1;
+            return;
+        }
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+
+        self.entries.push_back(entry);
+    }
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Trace::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.dropped > 0 {
+            writeln!(f, "dropped {}", self.dropped)?;
+        }
+
+        for entry in &self.entries {
+            writeln!(f, "{}", entry)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Trace {
+    type Err = ParseTraceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut trace = Trace::default();
+
+        for (index, line) in s.lines().enumerate() {
+            let error = ParseTraceError { line: index + 1 };
+
+            if line.trim().is_empty() {
+                continue;
+            } else if let Some(dropped) = line.trim().strip_prefix("dropped ") {
+                trace.dropped = dropped.parse().map_err(|_| error)?;
+            } else {
+                trace.entries.push_back(line.parse().map_err(|_| error)?);
+            }
+        }
+
+        // a parsed trace keeps everything it was given
+        trace.capacity = trace.capacity.max(trace.entries.len());
+        Ok(trace)
+    }
+}
+
+impl fmt::Display for ParseTraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid trace entry on line {}", self.line)
+    }
+}
+
+impl Error for ParseTraceError {}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Diverged {
+                step,
+                expected,
+                found,
+            } => write!(
+                f,
+                "diverged at step {}: expected '{}', found '{}'",
+                step, expected, found
+            ),
+            Self::Starved { step } => write!(
+                f,
+                "input requested at step {} but the trace doesn't provide any",
+                step
+            ),
+            Self::Fault { step, error } => write!(f, "VM error at step {}: {}", step, error),
+            Self::Truncated { dropped } => {
+                write!(f, "the trace is missing its first {} instructions", dropped)
+            }
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+// runs the VM against a recorded trace and returns where they first differ. inputs are taken
+// from the input instructions in the trace, so the VM doesn't need any queued up
+pub fn replay(vm: &mut IntcodeVM, trace: &Trace) -> Result<(), ReplayError> {
+    if trace.dropped > 0 {
+        return Err(ReplayError::Truncated {
+            dropped: trace.dropped,
+        });
+    }
+
+    let previous = vm.trace.replace(Trace::default());
+    let result = replay_entries(vm, trace);
+    vm.trace = previous;
+    result
+}
+
+fn replay_entries(vm: &mut IntcodeVM, trace: &Trace) -> Result<(), ReplayError> {
+    for (step, expected) in trace.entries.iter().enumerate() {
+        let found = loop {
+            match vm.step() {
+                Err(error) => return Err(ReplayError::Fault { step, error }),
+                Ok(Some(RunState::NeedsInput)) => match expected.input() {
+                    Some(value) => vm.input(value),
+                    None => return Err(ReplayError::Starved { step }),
+                },
+                Ok(_) => {
+                    if let Some(entry) =
+                        vm.trace.as_mut().and_then(|trace| trace.entries.pop_back())
+                    {
+                        break entry;
+                    }
+                }
+            }
+        };
+
+        if found != *expected {
+            return Err(ReplayError::Diverged {
+                step,
+                expected: Box::new(expected.clone()),
+                found: Box::new(found),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::tests::{outputs, vm},
+        replay, ReplayError, Trace,
+    };
+
+    const SOURCE: &str = "
+    loop:
+        INPUT [value]
+        MUL [value], #2, [value]
+        OUTPUT [value]
+        ADD [count], #-1, [count]
+        JUMP_IF_TRUE [count], #loop
+        HALT
+    value:
+        .data 0
+    count:
+        .data 3
+    ";
+
+    #[test]
+    fn traces_record_and_replay() {
+        let mut recorded = vm(SOURCE);
+        recorded.start_trace();
+        for value in 1..=3 {
+            recorded.input(value);
+        }
+
+        assert_eq!(outputs(&mut recorded), Ok(vec![2, 4, 6]));
+        let trace = recorded.take_trace().unwrap();
+        assert_eq!(trace.entries.len(), 16);
+        assert_eq!(trace.dropped, 0);
+        assert_eq!(trace.entries.back().unwrap().instruction(), "HALT");
+        let traced: Vec<_> = trace
+            .entries
+            .iter()
+            .filter_map(|entry| entry.output)
+            .collect();
+        assert_eq!(traced, vec![2, 4, 6]);
+
+        // the replay gets its input from the trace and ends up in the same place
+        let parsed: Trace = trace.to_string().parse().unwrap();
+        assert_eq!(parsed, trace);
+        let mut replayed = vm(SOURCE);
+        assert_eq!(replay(&mut replayed, &parsed), Ok(()));
+        for address in 0..18 {
+            assert_eq!(replayed.memory.get(address), recorded.memory.get(address));
+        }
+        assert_eq!(replayed.ip, recorded.ip);
+
+        let mut changed = vm(SOURCE);
+        changed.memory.set(4, 3);
+        assert!(matches!(
+            replay(&mut changed, &trace),
+            Err(ReplayError::Diverged { step: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn traces_keep_the_most_recent_instructions() {
+        let mut bounded = vm(SOURCE);
+        bounded.start_trace_with_capacity(4);
+        for value in 1..=3 {
+            bounded.input(value);
+        }
+
+        assert_eq!(outputs(&mut bounded), Ok(vec![2, 4, 6]));
+        let trace = bounded.take_trace().unwrap();
+        assert_eq!(trace.entries.len(), 4);
+        assert_eq!(trace.dropped as u64, bounded.steps() - 4);
+        assert_eq!(trace.entries.back().unwrap().instruction(), "HALT");
+        assert_eq!(
+            trace.to_string().parse::<Trace>().unwrap().dropped,
+            trace.dropped
+        );
+        assert_eq!(
+            replay(&mut vm(SOURCE), &trace),
+            Err(ReplayError::Truncated {
+                dropped: trace.dropped
+            })
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use std::ops::Range;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WatchHit {
+    pub watchpoint: usize,
+    pub ip: usize,
+    pub address: usize,
+    pub access: Access,
+    pub value: i64,
+}
+
+#[derive(Debug, Clone)]
+struct Watchpoint {
+    id: usize,
+    range: Range<usize>,
+    kind: WatchKind,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Watchpoints {
+    watchpoints: Vec<Watchpoint>,
+    next_id: usize,
+    // (watchpoint, address, access, value) for every hit during the current instruction
+    fired: Vec<(usize, usize, Access, i64)>,
+}
+
+impl WatchKind {
+    fn matches(self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (Self::Access, _) | (Self::Read, Access::Read) | (Self::Write, Access::Write)
+        )
+    }
+}
+
+impl Watchpoints {
+    pub(super) fn add(&mut self, range: Range<usize>, kind: WatchKind) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.watchpoints.push(Watchpoint { id, range, kind });
+        id
+    }
+
+    pub(super) fn remove(&mut self, id: usize) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+        self.watchpoints.len() != len
+    }
+
+    pub(super) fn check(&mut self, address: usize, access: Access, value: i64) {
+        for watchpoint in &self.watchpoints {
+            if watchpoint.kind.matches(access) && watchpoint.range.contains(&address) {
+                self.fired.push((watchpoint.id, address, access, value));
+            }
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    pub(super) fn has_fired(&self) -> bool {
+        !self.fired.is_empty()
+    }
+
+    pub(super) fn take_fired(&mut self, ip: usize) -> impl Iterator<Item = WatchHit> + '_ {
+        self.fired
+            .drain(..)
+            .map(move |(watchpoint, address, access, value)| WatchHit {
+                watchpoint,
+                ip,
+                address,
+                access,
+                value,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{tests::vm, RunState},
+        Access, WatchHit, WatchKind,
+    };
+
+    #[test]
+    fn watchpoints_pause_after_the_access() {
+        let source = "
+            ADD [a], #1, [b]
+            OUTPUT [b]
+            ADD [b], #1, [b]
+            HALT
+        a:
+            .data 5
+        b:
+            .data 0
+        ";
+        let hit = |watchpoint, ip, address, access, value| {
+            RunState::Watchpoint(WatchHit {
+                watchpoint,
+                ip,
+                address,
+                access,
+                value,
+            })
+        };
+
+        let mut watched = vm(source);
+        let writes = watched.add_watchpoint(12..13, WatchKind::Write);
+        let reads = watched.add_watchpoint(11..12, WatchKind::Read);
+
+        // the instruction has already run, but the next one hasn't
+        assert_eq!(watched.run(), Ok(hit(reads, 0, 11, Access::Read, 5)));
+        assert_eq!((watched.ip, watched.memory.get(12)), (4, 6));
+        assert_eq!(watched.run(), Ok(hit(writes, 0, 12, Access::Write, 6)));
+        assert_eq!(watched.ip, 4);
+
+        // resuming carries on with the next instruction
+        assert_eq!(watched.run(), Ok(RunState::Output(6)));
+        assert_eq!(watched.run(), Ok(hit(writes, 6, 12, Access::Write, 7)));
+        assert!(watched.remove_watchpoint(writes));
+        assert!(!watched.remove_watchpoint(writes));
+        assert_eq!(watched.run(), Ok(RunState::Halted));
+
+        // outputs come before the watchpoints their instruction tripped
+        let mut watched = vm(source);
+        let both = watched.add_watchpoint(12..13, WatchKind::Access);
+        assert_eq!(watched.run(), Ok(hit(both, 0, 12, Access::Write, 6)));
+        assert_eq!(watched.run(), Ok(RunState::Output(6)));
+        assert_eq!(watched.run(), Ok(hit(both, 4, 12, Access::Read, 6)));
+
+        // and runs that only care about outputs don't stop for them at all
+        assert_eq!(watched.get_next_output(), Ok(None));
+        assert_eq!(watched.memory.get(12), 7);
+    }
+}
@@ -2,7 +2,7 @@
 
 use aoc_runner_derive::aoc_lib;
 
-mod intcode;
+pub mod intcode;
 
 pub mod day1;
 pub mod day2;